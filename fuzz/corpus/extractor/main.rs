@@ -0,0 +1,31 @@
+extern crate crowbook_intl_runtime;
+#[macro_use] mod localize_macros;
+
+use std::io::{self, Write};
+
+// lformat!("commented out")
+/* lformat!("also commented out /* nested */") */
+static MENU: &[&str] = &[lmark!("Open"), lmark!("Save")];
+
+fn main() {
+    let name = "world";
+    lprintln!("Hello, {}!", name);
+    let url = lformat!("See http://example.org/{}", "doc");
+    let path = lformat!("C:\\Users\\{}\\", name);
+    let quoted = lformat!("\"{}\" isn't '{}'", 'a', '\'');
+    let long = lformat!(concat!("A message split ",
+                                "over several lines: {}"), 42);
+    let _ = writeln!(io::stdout(), "{}", lformat_args!("Lazy {}", url));
+    lwriteln!(io::stderr(), "Error: {}", lstr!("Cancel")).unwrap();
+    lwriteln!(&mut io::stdout());
+    let _ = lformat!(name);
+    let _ = (path, quoted, long, MENU);
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test() {
+        lformat!("only in tests");
+    }
+}
@@ -0,0 +1,28 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Feeds arbitrary input to the extractor, which must report diagnostics
+//! rather than panic on invalid source code.
+
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate crowbook_intl;
+
+use crowbook_intl::{Localizer, Extractor};
+
+use std::env;
+use std::fs;
+use std::process;
+
+fuzz_target!(|data: &[u8]| {
+    let file = env::temp_dir().join(format!("crowbook-intl-fuzz-{}.rs", process::id()));
+    fs::write(&file, data).unwrap();
+    let mut extractor = Extractor::new();
+    let res = extractor.add_messages_from_file(&file);
+    let _ = fs::remove_file(&file);
+    if res.is_ok() {
+        extractor.generate_pot_file();
+        Localizer::new(&extractor).generate_macro_file();
+    }
+});
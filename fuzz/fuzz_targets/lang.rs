@@ -0,0 +1,22 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Feeds arbitrary input to the `.po` parser, which must return an error
+//! rather than panic on invalid files.
+
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate crowbook_intl;
+
+use crowbook_intl::{Localizer, Extractor};
+
+fuzz_target!(|data: &[u8]| {
+    let s = String::from_utf8_lossy(data);
+    let extractor = Extractor::new();
+    let mut localizer = Localizer::new(&extractor);
+    if localizer.add_lang("fr", &s).is_ok() {
+        localizer.coverage();
+        localizer.generate_macro_file();
+    }
+});
@@ -4,58 +4,221 @@
 
 use error::{Error, Result};
 
-use std::borrow::Cow;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use regex::Regex;
 
-/// Escape some special characters that would cause trouble
+/// Finds the next string, delimited by double quotes, and returns the byte
+/// offsets of the beginning and end of its content (that is, excluding the quotes)
 ///
-/// The newline character
-/// '\' followed by a newline
-pub fn escape_string<'a, S:Into<Cow<'a, str>>>(s: S) -> Cow<'a, str> {
-    lazy_static! {
-        static ref REGEX:Regex = Regex::new(r#"\\\n\s*"#).unwrap();
+/// A backslash escapes the next character, so `\"` doesn't end the string but
+/// `\\"` does. This works for both Rust string literals and PO strings, whose
+/// escape sequences (but not raw strings) are all of this form.
+///
+/// Returns an error if there is no string, or if it isn't terminated.
+pub fn find_string_pos(s: &str) -> Result<(usize, usize)> {
+    let bytes = s.as_bytes();
+    let begin = match bytes.iter().position(|&b| b == b'"') {
+        Some(quote) => quote + 1,
+        None => return Err(Error::parse("expected a string between double quotes")),
+    };
+    let mut i = begin;
+    while i < bytes.len() {
+        match bytes[i] {
+            // Skipping a single byte is fine even before a multibyte character,
+            // since its other bytes can't be '"' or '\\'
+            b'\\' => i += 2,
+            b'"' => return Ok((begin, i)),
+            _ => i += 1,
+        }
     }
+    Err(Error::parse("unterminated string"))
+}
 
-    let s = s.into();
-    if s.contains('\n') || REGEX.is_match(&s) {
-        let mut res = REGEX.replace_all(&s, "").into_owned();
-        res = res.replace('\n', r"\n");
-        Cow::Owned(res)
-    } else {
-        s
+/// If `s` starts with a raw string literal (`r"..."`, `r#"..."#`, ...), returns
+/// the byte offsets of the beginning and end of its content, and of the end of
+/// the literal
+///
+/// Returns `None` if `s` doesn't start with a raw string, and an error if it
+/// isn't terminated.
+pub fn find_raw_string_pos(s: &str) -> Option<Result<(usize, usize, usize)>> {
+    if !s.starts_with('r') {
+        return None;
+    }
+    let hashes = s[1..].bytes().take_while(|&b| b == b'#').count();
+    if s.as_bytes().get(1 + hashes) != Some(&b'"') {
+        return None;
     }
+    let begin = hashes + 2;
+    let terminator = format!("\"{}", "#".repeat(hashes));
+    Some(match s[begin..].find(&terminator) {
+        Some(n) => Ok((begin, begin + n, begin + n + terminator.len())),
+        None => Err(Error::parse("unterminated raw string")),
+    })
 }
 
-/// Find the next string, delimited by quotes `"` (which are not returned),
-/// and not stopping at escape quotes `\"`
-pub fn find_string(bytes: &[u8]) -> Result<String> {
-    let mut begin = None;
-    let mut i = 0;
-    while i < bytes.len() {
-        match bytes[i] {
-            b'"' => if begin.is_some() {
-                if bytes[i-1] != b'\\' {
-                    break
+/// Returns true if s contains arguments, false else
+///
+/// A malformed format string is considered to contain arguments.
+pub fn has_arguments(s: &str) -> bool {
+    format_arguments(s)
+        .map(|args| !args.is_empty())
+        .unwrap_or(true)
+}
+
+/// Returns the (sorted, deduplicated) arguments used by a format string:
+/// positions (`"0"`, `"1"`, ...) for implicit and positional arguments, and
+/// names for named arguments
+///
+/// Returns an error if `s` is not a valid format string.
+pub fn format_arguments(s: &str) -> Result<Vec<String>> {
+    lazy_static! {
+        static ref ARGUMENT: Regex = Regex::new(r#"^([0-9]+|[A-Za-z_][A-Za-z0-9_]*)$"#).unwrap();
+        static ref COUNT: Regex = Regex::new(r#"([0-9]+|[A-Za-z_][A-Za-z0-9_]*)\$"#).unwrap();
+    }
+
+    let mut args = vec!();
+    let mut next = 0;
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' | '}' if chars.peek() == Some(&c) => {
+                chars.next();
+            },
+            '}' => return Err(Error::placeholder(format!("unmatched '}}' in format string \"{}\"", s))),
+            '{' => {
+                let mut inner = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some('{') | None => return Err(Error::placeholder(format!("unmatched '{{' in format string \"{}\"", s))),
+                        Some(c) => inner.push(c),
+                    }
+                }
+                let (arg, spec) = match inner.find(':') {
+                    Some(i) => (inner[..i].trim(), &inner[i + 1..]),
+                    None => (inner.trim(), ""),
+                };
+                for caps in COUNT.captures_iter(spec) {
+                    args.push(caps[1].to_owned());
+                }
+                if spec.contains(".*") {
+                    args.push(format!("{}", next));
+                    next += 1;
                 }
-            } else {
-                if i + 1 >= bytes.len() {
-                    return Err(Error::new(""));
+                if arg.is_empty() {
+                    args.push(format!("{}", next));
+                    next += 1;
+                } else if ARGUMENT.is_match(arg) {
+                    args.push(arg.to_owned());
+                } else {
+                    return Err(Error::placeholder(format!("invalid argument '{}' in format string \"{}\"", arg, s)));
                 }
-                begin = Some(i + 1);
             },
             _ => (),
         }
-        i += 1;
     }
-    let begin = if let Some(begin) = begin {
-        begin
-    } else {
-        return Err(Error::new(""));
-    };
-    Ok(String::from_utf8(bytes[begin..i].to_vec()).unwrap())
+    args.sort();
+    args.dedup();
+    Ok(args)
+}
+
+/// Atomically replace the content of `file` by `content`
+///
+/// The content is first written to a temporary file in the same directory,
+/// which is then renamed, so an interrupted build never leaves a half-written file.
+/// The temporary file's name is unique to the process and call, so concurrent
+/// builds writing the same file don't clobber each other's temporary file.
+pub fn write_atomic(file: &Path, content: &str) -> Result<()> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let file_name = file.file_name()
+        .ok_or_else(|| Error::new(format!("Could not create file {}: not a file name",
+                                          file.display())))?;
+    let tmp = file.with_file_name(format!(".{}.{}-{}.tmp",
+                                          file_name.to_string_lossy(),
+                                          process::id(),
+                                          COUNTER.fetch_add(1, Ordering::SeqCst)));
+    let res = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&tmp)
+        .map_err(|e| Error::io(format!("Could not create file {}", tmp.display()), e))
+        .and_then(|mut f| {
+            f.write_all(content.as_bytes())
+                .and_then(|_| f.sync_all())
+                .map_err(|e| Error::io(format!("Could not write to file {}", tmp.display()), e))
+        });
+    if let Err(e) = res {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    fs::rename(&tmp, file)
+        .map_err(|e| {
+            let _ = fs::remove_file(&tmp);
+            Error::io(format!("Could not rename {} to {}", tmp.display(), file.display()), e)
+        })
 }
 
+/// A uniquely named directory under `temp_dir()`, removed when dropped
+/// (even if the test using it fails)
+#[cfg(test)]
+pub struct TempDir(::std::path::PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let dir = ::std::env::temp_dir()
+            .join(format!("crowbook-intl-{}-{}-{}", name, process::id(),
+                          COUNTER.fetch_add(1, Ordering::SeqCst)));
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    /// Writes `content` to `file` (relative to this directory), creating
+    /// parent directories if needed
+    pub fn write(&self, file: &str, content: &str) -> &TempDir {
+        let path = self.0.join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+        self
+    }
+}
+
+#[cfg(test)]
+impl ::std::ops::Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Concatenates the tokens picked by each of the arbitrary bytes generated by
+/// quickcheck, so the input is close enough to a format to get past its first checks
+#[cfg(test)]
+pub fn from_tokens(bytes: &[u8], tokens: &[&str]) -> String {
+    bytes.iter().map(|b| tokens[*b as usize % tokens.len()]).collect()
+}
 
 #[test]
 fn find_string_1() {
@@ -63,7 +226,8 @@ fn find_string_1() {
 "Test"
 "#;
     let expected = "Test";
-    assert_eq!(&find_string(s.as_bytes()).unwrap(), expected);
+    let (begin, end) = find_string_pos(s).unwrap();
+    assert_eq!(&s[begin..end], expected);
 }
 
 #[test]
@@ -72,39 +236,87 @@ fn find_string_2() {
 "A \"test\"..."
 "#;
     let expected = r#"A \"test\"..."#;
-    assert_eq!(&find_string(s.as_bytes()).unwrap(), expected);
+    let (begin, end) = find_string_pos(s).unwrap();
+    assert_eq!(&s[begin..end], expected);
 }
 
 #[test]
-fn escape_string_1() {
-    let s = r#"foo
-bar"#;
-    let expected = "foo\\nbar";
-    assert_eq!(&escape_string(s), expected);
+fn find_raw_string() {
+    let s = r##"r#"a "b" c"#, x)"##;
+    let (begin, end, after) = find_raw_string_pos(s).unwrap().unwrap();
+    assert_eq!(&s[begin..end], r#"a "b" c"#);
+    assert_eq!(&s[..after], r##"r#"a "b" c"#"##);
+    assert_eq!(find_raw_string_pos(r#"r"C:\" x"#).unwrap().unwrap(), (2, 5, 6));
+    assert!(find_raw_string_pos(r##"r#"a"b"##).unwrap().is_err());
+    assert!(find_raw_string_pos(r#""foo""#).is_none());
+    assert!(find_raw_string_pos("r#foo").is_none());
+    assert!(find_raw_string_pos("result").is_none());
 }
 
 #[test]
-fn escape_string_2() {
-    let s = "foo\
-             bar";
-    let expected = "foobar";
-    assert_eq!(&escape_string(s), expected);
+#[allow(clippy::bool_assert_comparison)]
+fn test_arguments() {
+    assert_eq!(has_arguments("foo bar"), false);
+    assert_eq!(has_arguments("foo {}"), true);
+    assert_eq!(has_arguments("foo {{bar}}"), false);
 }
 
 #[test]
-fn escape_string_3() {
-    let s = r#"foo\
-             bar"#;
-    let expected = "foobar";
-    assert_eq!(&escape_string(s), expected);
+fn find_string_escapes() {
+    assert_eq!(find_string_pos(r#"msgstr "C:\\""#).unwrap(), (8, 12));
+    assert_eq!(find_string_pos(r#""a\"b\\\"c" d""#).unwrap(), (1, 10));
+    assert_eq!(find_string_pos(r#""é\é""#).unwrap(), (1, 6));
+    assert!(find_string_pos(r#""C:\""#).is_err());
+    assert!(find_string_pos(r#""foo"#).is_err());
+    assert!(find_string_pos("\"foo\\").is_err());
+    assert!(find_string_pos("\"").is_err());
+    assert!(find_string_pos("no string").is_err());
 }
 
+#[cfg(test)]
+quickcheck! {
+    fn find_string_po_round_trip(s: String, tail: String) -> bool {
+        let literal = format!("msgid \"{}\"{}", ::escape::escape_po(&s), tail);
+        match find_string_pos(&literal) {
+            Ok((begin, end)) => ::escape::unescape_po(&literal[begin..end]).ok() == Some(s),
+            Err(_) => false,
+        }
+    }
+
+    fn find_string_rust_round_trip(s: String) -> bool {
+        let literal = format!("lformat!(\"{}\", x)", ::escape::escape_rust(&s));
+        match find_string_pos(&literal) {
+            Ok((begin, end)) => ::escape::unescape_rust(&literal[begin..end]).ok() == Some(s),
+            Err(_) => false,
+        }
+    }
+
+    fn find_string_arbitrary(bytes: Vec<u8>) -> bool {
+        let s = String::from_utf8_lossy(&bytes);
+        match find_string_pos(&s) {
+            Ok((begin, end)) => s.is_char_boundary(begin) && s.is_char_boundary(end)
+                && s[end..].starts_with('"'),
+            Err(_) => true,
+        }
+    }
+}
 
 #[test]
-fn escape_string_4() {
-    let s = r#"foo\
-             bar
-baz"#;
-    let expected = "foobar\\nbaz";
-    assert_eq!(&escape_string(s), expected);
+fn write_atomic_concurrent() {
+    let dir = TempDir::new("write-atomic");
+    let file = dir.join("default.pot");
+    let threads = (0..8)
+        .map(|i| {
+            let file = file.clone();
+            ::std::thread::spawn(move || write_atomic(&file, &i.to_string().repeat(10000)))
+        })
+        .collect::<Vec<_>>();
+    for thread in threads {
+        thread.join().unwrap().unwrap();
+    }
+    // The file has the whole content written by one of the threads
+    let content = fs::read_to_string(&file).unwrap();
+    assert_eq!(content.len(), 10000);
+    assert!(content.chars().all(|c| content.starts_with(c)));
+    assert_eq!(fs::read_dir(&*dir).unwrap().count(), 1);
 }
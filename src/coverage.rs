@@ -0,0 +1,226 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Number of translated, fuzzy and untranslated items (messages or words)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counts {
+    pub translated: usize,
+    /// Translated, but marked as `fuzzy` in the `.po` file
+    pub fuzzy: usize,
+    pub untranslated: usize,
+}
+
+impl Counts {
+    /// Returns the total number of items
+    pub fn total(&self) -> usize {
+        self.translated + self.fuzzy + self.untranslated
+    }
+
+    /// Returns the percentage of translated items (100 if there are none)
+    pub fn percent(&self) -> usize {
+        match self.total() {
+            0 => 100,
+            total => self.translated * 100 / total,
+        }
+    }
+}
+
+/// Status of the translation of a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Translated,
+    Fuzzy,
+    Untranslated,
+}
+
+/// Translation statistics of a set of messages
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub messages: Counts,
+    /// Counts of words of the original messages
+    pub words: Counts,
+}
+
+impl Stats {
+    /// Adds a message to the statistics
+    pub fn add(&mut self, msg: &str, status: Status) {
+        let words = msg.split_whitespace().count();
+        let (messages, word_counts) = match status {
+            Status::Translated => (&mut self.messages.translated, &mut self.words.translated),
+            Status::Fuzzy => (&mut self.messages.fuzzy, &mut self.words.fuzzy),
+            Status::Untranslated => (&mut self.messages.untranslated, &mut self.words.untranslated),
+        };
+        *messages += 1;
+        *word_counts += words;
+    }
+}
+
+/// Translation statistics of a language
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LangCoverage {
+    /// The lang code
+    pub lang: String,
+    /// Statistics for all messages
+    pub total: Stats,
+    /// Statistics for the messages found in each source file (a message found in
+    /// several files counts in each of them)
+    pub files: BTreeMap<String, Stats>,
+}
+
+/// Translation coverage report, returned by `Localizer::coverage`
+///
+/// It can be rendered as plain text (with `Display`), as Markdown or as JSON.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Coverage {
+    pub langs: Vec<LangCoverage>,
+}
+
+impl Coverage {
+    /// Renders the report as Markdown, with a table per language
+    pub fn to_markdown(&self) -> String {
+        let mut output = String::new();
+        for lang in &self.langs {
+            writeln!(output, "## {}\n", lang.lang).unwrap();
+            writeln!(output, "| File | Translated | Fuzzy | Untranslated | Words | Progress |").unwrap();
+            writeln!(output, "|------|-----------:|------:|-------------:|------:|---------:|").unwrap();
+            let rows = Some(("**Total**", &lang.total)).into_iter()
+                .chain(lang.files.iter().map(|(file, stats)| (file.as_str(), stats)));
+            for (name, stats) in rows {
+                writeln!(output, "| {} | {} | {} | {} | {}/{} | {}% |",
+                         name.replace('|', "\\|"),
+                         stats.messages.translated,
+                         stats.messages.fuzzy,
+                         stats.messages.untranslated,
+                         stats.words.translated,
+                         stats.words.total(),
+                         stats.messages.percent()).unwrap();
+            }
+            output.push('\n');
+        }
+        output
+    }
+
+    /// Renders the report as JSON
+    pub fn to_json(&self) -> String {
+        let mut output = String::from("[");
+        for (i, lang) in self.langs.iter().enumerate() {
+            if i > 0 {
+                output.push(',');
+            }
+            write!(output, "{{\"lang\":{},\"total\":{},\"files\":{{",
+                   json_string(&lang.lang), json_stats(&lang.total)).unwrap();
+            for (j, (file, stats)) in lang.files.iter().enumerate() {
+                if j > 0 {
+                    output.push(',');
+                }
+                write!(output, "{}:{}", json_string(file), json_stats(stats)).unwrap();
+            }
+            output.push_str("}}");
+        }
+        output.push(']');
+        output
+    }
+}
+
+impl ::std::fmt::Display for Coverage {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        for lang in &self.langs {
+            writeln!(f, "{}: {}", lang.lang, text_stats(&lang.total))?;
+            for (file, stats) in &lang.files {
+                writeln!(f, "  {}: {}", file, text_stats(stats))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Renders statistics on a line of text
+fn text_stats(stats: &Stats) -> String {
+    format!("{}/{} messages translated ({}%), {} fuzzy, {} untranslated; {}/{} words translated",
+            stats.messages.translated,
+            stats.messages.total(),
+            stats.messages.percent(),
+            stats.messages.fuzzy,
+            stats.messages.untranslated,
+            stats.words.translated,
+            stats.words.total())
+}
+
+/// Renders counts as a JSON object
+fn json_counts(counts: &Counts) -> String {
+    format!("{{\"translated\":{},\"fuzzy\":{},\"untranslated\":{},\"total\":{}}}",
+            counts.translated, counts.fuzzy, counts.untranslated, counts.total())
+}
+
+/// Renders statistics as a JSON object
+fn json_stats(stats: &Stats) -> String {
+    format!("{{\"messages\":{},\"words\":{}}}",
+            json_counts(&stats.messages), json_counts(&stats.words))
+}
+
+/// Renders a JSON string literal
+fn json_string(s: &str) -> String {
+    let mut res = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            c if (c as u32) < 0x20 => res.push_str(&format!("\\u{:04x}", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+
+#[cfg(test)]
+fn sample_coverage() -> Coverage {
+    let mut total = Stats::default();
+    let mut file = Stats::default();
+    total.add("Hello world", Status::Translated);
+    total.add("Cancel", Status::Fuzzy);
+    total.add("Open the \"file\"", Status::Untranslated);
+    file.add("Hello world", Status::Translated);
+    let mut files = BTreeMap::new();
+    files.insert(String::from("src/main.rs"), file);
+    Coverage {
+        langs: vec!(LangCoverage {
+            lang: String::from("fr"),
+            total,
+            files,
+        }),
+    }
+}
+
+#[test]
+fn coverage_counts() {
+    let coverage = sample_coverage();
+    let total = &coverage.langs[0].total;
+    assert_eq!(total.messages, Counts { translated: 1, fuzzy: 1, untranslated: 1 });
+    assert_eq!(total.words, Counts { translated: 2, fuzzy: 1, untranslated: 3 });
+    assert_eq!(total.messages.percent(), 33);
+    assert_eq!(Counts::default().percent(), 100);
+}
+
+#[test]
+fn coverage_render() {
+    let coverage = sample_coverage();
+    assert_eq!(&format!("{}", coverage),
+               "fr: 1/3 messages translated (33%), 1 fuzzy, 1 untranslated; 2/6 words translated\n  \
+                src/main.rs: 1/1 messages translated (100%), 0 fuzzy, 0 untranslated; 2/2 words translated\n");
+    assert!(coverage.to_markdown().contains("| src/main.rs | 1 | 0 | 0 | 2/2 | 100% |\n"));
+    assert_eq!(&coverage.to_json(),
+               "[{\"lang\":\"fr\",\"total\":{\"messages\":{\"translated\":1,\"fuzzy\":1,\"untranslated\":1,\"total\":3},\
+                \"words\":{\"translated\":2,\"fuzzy\":1,\"untranslated\":3,\"total\":6}},\
+                \"files\":{\"src/main.rs\":{\"messages\":{\"translated\":1,\"fuzzy\":0,\"untranslated\":0,\"total\":1},\
+                \"words\":{\"translated\":2,\"fuzzy\":0,\"untranslated\":0,\"total\":2}}}}]");
+    assert_eq!(&json_string("a\"b\\\n\u{1}"), "\"a\\\"b\\\\\\n\\u0001\"");
+}
@@ -0,0 +1,73 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::fmt;
+
+/// Severity of a diagnostic
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Something suspicious, but the extraction went on
+    Warning,
+    /// A message or file could not be extracted
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A problem encountered while extracting messages from source code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Path of the source file
+    pub file: String,
+    /// Line of the problem (starting at 1, or 0 if it concerns the whole file)
+    pub line: usize,
+    /// Column of the problem, in characters (starting at 1, or 0 if unknown)
+    pub column: usize,
+    /// Description of the problem
+    pub reason: String,
+    pub severity: Severity,
+}
+
+impl Diagnostic {
+    /// Creates a new diagnostic
+    pub fn new<S1, S2>(severity: Severity, file: S1, line: usize, column: usize, reason: S2) -> Diagnostic
+        where S1: Into<String>,
+              S2: Into<String> {
+        Diagnostic {
+            file: file.into(),
+            line,
+            column,
+            reason: reason.into(),
+            severity,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.file)?;
+        if self.line > 0 {
+            write!(f, ":{}", self.line)?;
+            if self.column > 0 {
+                write!(f, ":{}", self.column)?;
+            }
+        }
+        write!(f, ": {}: {}", self.severity, self.reason)
+    }
+}
+
+#[test]
+fn diagnostic_display() {
+    let d = Diagnostic::new(Severity::Error, "src/main.rs", 12, 5, "could not parse as string");
+    assert_eq!(&format!("{}", d), "src/main.rs:12:5: error: could not parse as string");
+    let d = Diagnostic::new(Severity::Warning, "src/main.rs", 0, 0, "oops");
+    assert_eq!(&format!("{}", d), "src/main.rs: warning: oops");
+}
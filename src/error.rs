@@ -5,39 +5,178 @@
 use std::error;
 use std::result;
 use std::fmt;
+use std::io;
 
-/// Internal ErrorType
-#[derive(Debug, PartialEq)]
-enum ErrorType {
-    Default,
+/// Kind of an error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Reading or writing a file failed
+    Io,
+    /// A `.po` file or some source code could not be parsed
     Parse,
+    /// A translation doesn't use the same format arguments as the original
+    /// message, or a format string is invalid
+    Placeholder,
+    /// A string contains invalid escape sequences or isn't valid UTF-8
+    Encoding,
+    /// Any other error (e.g. an invalid glob pattern)
+    Other,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ErrorKind::Io => write!(f, "I/O error"),
+            ErrorKind::Parse => write!(f, "parse error"),
+            ErrorKind::Placeholder => write!(f, "placeholder error"),
+            ErrorKind::Encoding => write!(f, "encoding error"),
+            ErrorKind::Other => write!(f, "error"),
+        }
+    }
 }
 
 /// Result type (returned by most methods of this library)
 pub type Result<T> = result::Result<T, Error>;
 
-#[derive(Debug, PartialEq)]
 /// Error type returned by methods of this library
+///
+/// Besides a message, an error has a kind, can have an underlying error (e.g. an
+/// `io::Error`, available through `std::error::Error::source` but not displayed),
+/// and can record the file, line and column where it happened, as well as the
+/// content of this line. Its `Display` implementation then looks like `rustc`'s:
+///
+/// ```text
+/// parse error: expected a string, found 'Bonjour'
+///  --> lang/fr.po:3:8
+///   |
+/// 3 | msgstr Bonjour
+///   |        ^
+/// ```
+#[derive(Debug)]
 pub struct Error {
+    kind: ErrorKind,
     msg: String,
-    variant: ErrorType
+    // Boxed, so results stay small
+    position: Option<Box<Position>>,
+    source: Option<Box<dyn error::Error + Send + Sync>>,
+}
+
+/// Where an error happened
+#[derive(Debug, Default, PartialEq)]
+struct Position {
+    file: Option<String>,
+    line: Option<usize>,
+    column: Option<usize>,
+    snippet: Option<String>,
 }
 
 impl Error {
-    /// Creates a new default error
-    pub fn new<S: Into<String>>(msg: S) -> Error {
+    /// Creates a new error of the given kind
+    pub fn with_kind<S: Into<String>>(kind: ErrorKind, msg: S) -> Error {
         Error {
+            kind,
             msg: msg.into(),
-            variant: ErrorType::Default,
+            position: None,
+            source: None,
         }
     }
 
+    /// Creates a new default error
+    pub fn new<S: Into<String>>(msg: S) -> Error {
+        Error::with_kind(ErrorKind::Other, msg)
+    }
+
     /// Creates a new parse error
     pub fn parse<S: Into<String>>(msg: S) -> Error {
-        Error {
-            msg: msg.into(),
-            variant: ErrorType::Parse,
+        Error::with_kind(ErrorKind::Parse, msg)
+    }
+
+    /// Creates a new I/O error, caused by `source`
+    pub fn io<S: Into<String>>(msg: S, source: io::Error) -> Error {
+        Error::with_kind(ErrorKind::Io, msg).with_source(source)
+    }
+
+    /// Creates a new placeholder error
+    pub fn placeholder<S: Into<String>>(msg: S) -> Error {
+        Error::with_kind(ErrorKind::Placeholder, msg)
+    }
+
+    /// Creates a new encoding error
+    pub fn encoding<S: Into<String>>(msg: S) -> Error {
+        Error::with_kind(ErrorKind::Encoding, msg)
+    }
+
+    /// Sets the file where the error happened
+    pub fn with_file<S: Into<String>>(mut self, file: S) -> Error {
+        self.position_mut().file = Some(file.into());
+        self
+    }
+
+    /// Sets the line and column (starting at 1, 0 if unknown) where the error happened
+    pub fn with_position(mut self, line: usize, column: usize) -> Error {
+        {
+            let position = self.position_mut();
+            position.line = if line > 0 { Some(line) } else { None };
+            position.column = if column > 0 { Some(column) } else { None };
         }
+        self
+    }
+
+    /// Sets the content of the line where the error happened
+    pub fn with_snippet<S: Into<String>>(mut self, snippet: S) -> Error {
+        self.position_mut().snippet = Some(snippet.into());
+        self
+    }
+
+    fn position_mut(&mut self) -> &mut Position {
+        self.position.get_or_insert_with(Box::default)
+    }
+
+    /// Sets the underlying error
+    pub fn with_source<E>(mut self, source: E) -> Error
+        where E: Into<Box<dyn error::Error + Send + Sync>> {
+        self.source = Some(source.into());
+        self
+    }
+
+    /// Returns the kind of this error
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Returns the message of this error, without location
+    pub fn message(&self) -> &str {
+        &self.msg
+    }
+
+    /// Returns the file where the error happened, if known
+    pub fn file(&self) -> Option<&str> {
+        self.position.as_ref().and_then(|p| p.file.as_deref())
+    }
+
+    /// Returns the line (starting at 1) where the error happened, if known
+    pub fn line(&self) -> Option<usize> {
+        self.position.as_ref().and_then(|p| p.line)
+    }
+
+    /// Returns the column (in characters, starting at 1) where the error happened, if known
+    pub fn column(&self) -> Option<usize> {
+        self.position.as_ref().and_then(|p| p.column)
+    }
+
+    /// Returns the content of the line where the error happened, if known
+    pub fn snippet(&self) -> Option<&str> {
+        self.position.as_ref().and_then(|p| p.snippet.as_deref())
+    }
+}
+
+impl PartialEq for Error {
+    /// Errors are equal if they have the same kind, message and location
+    /// (their sources are not compared)
+    fn eq(&self, other: &Error) -> bool {
+        self.kind == other.kind
+            && self.msg == other.msg
+            && self.position == other.position
     }
 }
 
@@ -45,14 +184,70 @@ impl error::Error for Error {
     fn description(&self) -> &str {
         &self.msg
     }
+
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self.source {
+            Some(ref source) => Some(source.as_ref()),
+            None => None,
+        }
+    }
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self.variant {
-            ErrorType::Parse => write!(f, "Error parsing localization file: {}", self.msg),
-            _ => write!(f, "{}", self.msg),
+        // The source isn't displayed, since it is available through `source()`
+        write!(f, "{}: {}", self.kind, self.msg)?;
+        let position = match self.position {
+            Some(ref position) => position,
+            None => return Ok(()),
+        };
+        let line = position.line.map(|l| l.to_string()).unwrap_or_default();
+        let margin = " ".repeat(line.len());
+        write!(f, "\n{}--> {}", margin, position.file.as_deref().unwrap_or("<input>"))?;
+        if let Some(l) = position.line {
+            write!(f, ":{}", l)?;
+            if let Some(c) = position.column {
+                write!(f, ":{}", c)?;
+            }
+        }
+        if let Some(ref snippet) = position.snippet {
+            write!(f, "\n{} |\n{} | {}", margin, line, snippet)?;
+            if let Some(c) = position.column {
+                let padding = snippet.chars()
+                    .take(c - 1)
+                    .map(|c| if c == '\t' { '\t' } else { ' ' })
+                    .collect::<String>();
+                write!(f, "\n{} | {}^", margin, padding)?;
+            }
         }
+        Ok(())
     }
 }
 
+
+#[test]
+fn error_display() {
+    assert_eq!(&format!("{}", Error::new("oops")), "error: oops");
+    let e = Error::parse("expected a string, found 'Bonjour'")
+        .with_file("lang/fr.po")
+        .with_position(3, 8)
+        .with_snippet("msgstr Bonjour");
+    assert_eq!(&format!("{}", e),
+               "parse error: expected a string, found 'Bonjour'
+ --> lang/fr.po:3:8
+  |
+3 | msgstr Bonjour
+  |        ^");
+    let e = Error::placeholder("wrong arguments").with_position(12, 0);
+    assert_eq!(&format!("{}", e), "placeholder error: wrong arguments\n  --> <input>:12");
+}
+
+#[test]
+fn error_source() {
+    use std::error::Error as StdError;
+    let e = Error::io("could not read foo", io::Error::new(io::ErrorKind::NotFound, "not found"));
+    assert_eq!(e.kind(), ErrorKind::Io);
+    assert_eq!(&format!("{}", e.source().unwrap()), "not found");
+    assert_eq!(&format!("{}", e), "I/O error: could not read foo");
+    assert!(Error::new("foo").source().is_none());
+}
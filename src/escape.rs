@@ -0,0 +1,268 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Conversions between the three ways a message can be written:
+//!
+//! * as the content of a Rust string literal (e.g. `Caf\u{e9}\n`), as found in
+//!   `lformat!` invocations and as emitted in generated code;
+//! * as canonical text, i.e. the actual value of the string, which is what
+//!   `Message` and `Lang` store;
+//! * as the content of a PO string (e.g. `Café\n`), as written to `.pot` files
+//!   and read from `.po` files.
+
+use error::{Error, Result};
+
+use std::char;
+
+/// Converts the content of a Rust (non-raw) string literal to canonical text
+///
+/// Handles all escape sequences allowed by Rust, including a backslash at end of
+/// line, which skips the newline and the leading whitespace of the next line.
+pub fn unescape_rust(s: &str) -> Result<String> {
+    let mut res = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') => res.push('\n'),
+                Some('r') => res.push('\r'),
+                Some('t') => res.push('\t'),
+                Some('0') => res.push('\0'),
+                Some('\\') => res.push('\\'),
+                Some('\'') => res.push('\''),
+                Some('"') => res.push('"'),
+                Some('x') => {
+                    let hex: String = chars.by_ref().take(2).collect();
+                    match u8::from_str_radix(&hex, 16) {
+                        Ok(b) if hex.len() == 2 && b <= 0x7f => res.push(b as char),
+                        _ => return Err(Error::encoding(format!("invalid escape sequence '\\x{}'", hex))),
+                    }
+                },
+                Some('u') => {
+                    if chars.next() != Some('{') {
+                        return Err(Error::encoding("invalid unicode escape: expected '{' after '\\u'"));
+                    }
+                    let mut hex = String::new();
+                    let mut closed = false;
+                    for c in chars.by_ref() {
+                        if c == '}' {
+                            closed = true;
+                            break;
+                        }
+                        hex.push(c);
+                    }
+                    let digits = hex.replace('_', "");
+                    match u32::from_str_radix(&digits, 16).ok().and_then(char::from_u32) {
+                        Some(c) if closed && !digits.is_empty() && digits.len() <= 6 => res.push(c),
+                        _ => return Err(Error::encoding(format!("invalid unicode escape '\\u{{{}}}'", hex))),
+                    }
+                },
+                Some('\n') | Some('\r') => {
+                    while let Some(&c) = chars.peek() {
+                        if c == ' ' || c == '\t' || c == '\n' || c == '\r' {
+                            chars.next();
+                        } else {
+                            break;
+                        }
+                    }
+                },
+                Some(c) => return Err(Error::encoding(format!("unknown escape sequence '\\{}'", c))),
+                None => return Err(Error::encoding("string ends with a lone '\\'")),
+            },
+            '\r' if chars.peek() == Some(&'\n') => (),
+            c => res.push(c),
+        }
+    }
+    Ok(res)
+}
+
+/// Converts canonical text to the content of a Rust string literal
+///
+/// The result can safely be put between quotes in generated code.
+pub fn escape_rust(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => res.push_str(r"\\"),
+            '"' => res.push_str("\\\""),
+            '\n' => res.push_str(r"\n"),
+            '\r' => res.push_str(r"\r"),
+            '\t' => res.push_str(r"\t"),
+            '\0' => res.push_str(r"\0"),
+            c if c.is_control() => res.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+/// Converts the content of a PO string to canonical text
+///
+/// Handles the C escape sequences understood by gettext. Octal and hexadecimal
+/// escapes stand for bytes, so e.g. `\303\251` is `é`; returns an error if the
+/// resulting string isn't valid UTF-8.
+pub fn unescape_po(s: &str) -> Result<String> {
+    let mut res = Vec::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            res.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('n') => res.push(b'\n'),
+            Some('r') => res.push(b'\r'),
+            Some('t') => res.push(b'\t'),
+            Some('a') => res.push(b'\x07'),
+            Some('b') => res.push(b'\x08'),
+            Some('f') => res.push(b'\x0c'),
+            Some('v') => res.push(b'\x0b'),
+            Some('\\') => res.push(b'\\'),
+            Some('"') => res.push(b'"'),
+            Some('\'') => res.push(b'\''),
+            Some('?') => res.push(b'?'),
+            Some(c @ '0'..='7') => {
+                let mut n = c.to_digit(8).unwrap();
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(d) => {
+                            n = n * 8 + d;
+                            chars.next();
+                        },
+                        None => break,
+                    }
+                }
+                if n > 0xff {
+                    return Err(Error::encoding(format!("octal escape sequence '\\{:o}' is out of range", n)));
+                }
+                res.push(n as u8);
+            },
+            Some('x') => {
+                let mut hex = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_ascii_hexdigit() && hex.len() < 2 {
+                        hex.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(n) => res.push(n),
+                    Err(_) => return Err(Error::encoding("invalid escape sequence '\\x' without hex digits")),
+                }
+            },
+            Some(c) => return Err(Error::encoding(format!("unknown escape sequence '\\{}'", c))),
+            None => return Err(Error::encoding("string ends with a lone '\\'")),
+        }
+    }
+    String::from_utf8(res)
+        .map_err(|e| Error::encoding("escape sequences don't form valid UTF-8").with_source(e))
+}
+
+/// Converts canonical text to the content of a PO string
+pub fn escape_po(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => res.push_str(r"\\"),
+            '"' => res.push_str("\\\""),
+            '\n' => res.push_str(r"\n"),
+            '\r' => res.push_str(r"\r"),
+            '\t' => res.push_str(r"\t"),
+            '\x07' => res.push_str(r"\a"),
+            '\x08' => res.push_str(r"\b"),
+            '\x0b' => res.push_str(r"\v"),
+            '\x0c' => res.push_str(r"\f"),
+            c if (c as u32) < 0x20 || c == '\x7f' => res.push_str(&format!("\\{:03o}", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+
+#[test]
+fn unescape_rust_1() {
+    assert_eq!(unescape_rust(r#"a\tb\\c\"d\'e\x41\u{e9}\u{1F_600}\0"#).unwrap(),
+               "a\tb\\c\"d'eAé\u{1F600}\0");
+}
+
+#[test]
+fn unescape_rust_newline() {
+    let s = r#"foo
+bar"#;
+    assert_eq!(unescape_rust(s).unwrap(), "foo\nbar");
+}
+
+#[test]
+fn unescape_rust_continuation() {
+    let s = r#"foo\
+             bar
+baz"#;
+    assert_eq!(unescape_rust(s).unwrap(), "foobar\nbaz");
+    assert_eq!(unescape_rust("foo\\\r\n   bar").unwrap(), "foobar");
+}
+
+#[test]
+fn unescape_rust_invalid() {
+    assert!(unescape_rust(r"\x80").is_err());
+    assert!(unescape_rust(r"\x4").is_err());
+    assert!(unescape_rust(r"\u{110000}").is_err());
+    assert!(unescape_rust(r"\u{}").is_err());
+    assert!(unescape_rust(r"\u{41").is_err());
+    assert!(unescape_rust(r"\q").is_err());
+    assert!(unescape_rust("foo\\").is_err());
+}
+
+#[test]
+fn escape_rust_1() {
+    assert_eq!(&escape_rust("C:\\ \"quoted\"\n\t\u{7}é"), r#"C:\\ \"quoted\"\n\t\u{7}é"#);
+}
+
+#[test]
+fn unescape_po_1() {
+    assert_eq!(unescape_po(r#"a\tb\\c\"d\n\101\x42\a"#).unwrap(), "a\tb\\c\"d\nAB\x07");
+    assert!(unescape_po(r"\q").is_err());
+    assert_eq!(unescape_po(r"Caf\xc3\xa9 \342\202\254").unwrap(), "Café €");
+    assert!(unescape_po(r"\xe9").is_err());
+    assert!(unescape_po(r"\777").is_err());
+    assert!(unescape_po("foo\\").is_err());
+}
+
+#[test]
+fn escape_po_1() {
+    assert_eq!(&escape_po("C:\\ \"quoted\"\n\t\x07\x01é"), r#"C:\\ \"quoted\"\n\t\a\001é"#);
+}
+
+#[test]
+fn rust_to_po() {
+    assert_eq!(&escape_po(&unescape_rust(r"Caf\u{e9}\n").unwrap()), r"Café\n");
+    assert_eq!(&escape_po(&unescape_rust(r"It\'s").unwrap()), "It's");
+    assert_eq!(&escape_po(&unescape_rust(r"\x41\\").unwrap()), r"A\\");
+}
+
+#[cfg(test)]
+quickcheck! {
+    fn rust_round_trip(s: String) -> bool {
+        unescape_rust(&escape_rust(&s)).unwrap() == s
+    }
+
+    fn po_round_trip(s: String) -> bool {
+        unescape_po(&escape_po(&s)).unwrap() == s
+    }
+
+    fn escape_rust_has_no_lone_quote(s: String) -> bool {
+        let escaped = escape_rust(&s);
+        let mut backslashes = 0;
+        for c in escaped.chars() {
+            if c == '"' && backslashes % 2 == 0 {
+                return false;
+            }
+            backslashes = if c == '\\' { backslashes + 1 } else { 0 };
+        }
+        backslashes % 2 == 0
+    }
+}
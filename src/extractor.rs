@@ -2,18 +2,48 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with
 // this file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use message::Message;
-use error::{Error, Result};
-use common::{find_string, escape_string};
+use message::{Message, Location, AddLocation};
+use error::{Error, ErrorKind, Result};
+use common::{find_raw_string_pos, find_string_pos, has_arguments, write_atomic};
+#[cfg(test)] use common::{from_tokens, TempDir};
+use escape::{escape_rust, unescape_rust};
+use macrogen::{self, COMPANIONS};
+use potheader::PotHeader;
+use diagnostic::{Diagnostic, Severity};
 
 use std::collections::HashMap;
-use std::path::Path;
-use std::fs::File;
-use std::io::Read;
-use std::io::Write;
+use std::path::{Path, PathBuf, Component};
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::error::Error as StdError;
+use std::mem;
 
-use regex::Regex;
-use walkdir::WalkDir;
+use regex::{self, Regex};
+use ignore::WalkBuilder;
+use ignore::overrides::OverrideBuilder;
+
+/// Order in which messages are written to the `.pot` file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Sort by source file and line of the message's occurrences (default)
+    Source,
+    /// Sort alphabetically by message string
+    Msgid,
+    /// Keep the order in which messages were first found
+    FirstOccurrence,
+}
+
+/// Name of the domain messages belong to unless configured otherwise
+const DEFAULT_DOMAIN: &str = "default";
+
+/// Messages of a text domain
+#[derive(Debug, Clone, Default)]
+struct Catalog {
+    messages: HashMap<String, Message>,
+    // Keys of `messages`, in the order they were first found
+    order: Vec<String>,
+}
 
 /// Struct that extracts all messages from source code and can print them
 /// to a `.pot` file.
@@ -34,69 +64,590 @@ use walkdir::WalkDir;
 ///
 /// This struct only add messages that are considered as needing localization,
 /// that is, the first argument of calls so `lformat!` macro.
+///
+/// # Domains
+///
+/// Messages can be split into several text domains (e.g. one per team or
+/// component), either by directory (`add_domain_dir`) or by macro
+/// (`add_domain_macro`), and each domain written to its own `.pot` file
+/// with `write_pot_files`. Messages that aren't assigned a domain belong to
+/// the `"default"` one, which is the one `messages` and `generate_pot_file`
+/// work on.
 #[derive(Debug, Clone)]
 pub struct Extractor {
-    messages: HashMap<String, Message>,
-    // Matches the format string (as used by `lformat!` and the actual escaped string
-    // given to potfile
+    // Extracted messages, by domain
+    catalogs: HashMap<String, Catalog>,
+    // Directories (relative to the base directory) whose messages go to a domain
+    domain_dirs: Vec<(String, PathBuf)>,
+    // Macros whose messages go to a domain
+    domain_macros: HashMap<String, String>,
+    // Matches the format string (as used by `lformat!`) and the actual message
+    // when it isn't written as `escape_rust` would
     orig_strings: HashMap<String, String>, 
+    // Matches the string literals of a `concat!` used by `lformat!` to the
+    // concatenated message
+    concat_strings: HashMap<Vec<String>, String>,
+    // Matches the raw string literals used by `lformat!` (including the `r#"`
+    // and `"#` delimiters) to the message
+    raw_strings: HashMap<String, String>,
+    // Ignore volatile header fields when checking if the pot file changed
+    ignore_volatile_headers: bool,
+    header: PotHeader,
+    // Flag added to messages containing format arguments
+    format_flag: Option<String>,
+    sort_order: SortOrder,
+    // Source paths are written relative to this directory
+    base_dir: Option<PathBuf>,
+    add_location: AddLocation,
+    // Glob patterns of files to include/exclude when walking directories
+    include: Vec<String>,
+    exclude: Vec<String>,
+    respect_gitignore: bool,
+    follow_links: bool,
+    // Skip `#[cfg(test)]` modules
+    skip_test_modules: bool,
+    // Return an error on the first problem instead of recording a diagnostic
+    strict: bool,
+    diagnostics: Vec<Diagnostic>,
+    // Names of the macros whose first argument is extracted
+    macro_names: Vec<String>,
+    // Names of the macros whose second argument is extracted (the first one
+    // being the destination, as in `write!`)
+    writer_macro_names: Vec<String>,
+    // Names of the macros whose message is not a format string
+    literal_macro_names: Vec<String>,
+    // Matches calls to the macros above, built when first needed after they change
+    find_msgs: Option<Regex>,
+}
+
+impl Default for Extractor {
+    fn default() -> Extractor {
+        Extractor::new()
+    }
 }
 
 impl Extractor {
     /// Create a new, empty extractor
     pub fn new() -> Extractor {
         Extractor {
-            messages: HashMap::new(),
+            catalogs: vec!((String::from(DEFAULT_DOMAIN), Catalog::default())).into_iter().collect(),
+            domain_dirs: vec!(),
+            domain_macros: HashMap::new(),
             orig_strings: HashMap::new(), 
+            concat_strings: HashMap::new(),
+            raw_strings: HashMap::new(),
+            ignore_volatile_headers: true,
+            header: PotHeader::from_env(),
+            format_flag: Some(String::from("rust-format")),
+            sort_order: SortOrder::Source,
+            base_dir: env::var_os("CARGO_MANIFEST_DIR").map(PathBuf::from),
+            add_location: AddLocation::Full,
+            include: vec!(),
+            exclude: vec!(),
+            respect_gitignore: true,
+            follow_links: false,
+            skip_test_modules: false,
+            strict: false,
+            diagnostics: vec!(),
+            macro_names: ["lformat", "lformat_args", "lprint", "lprintln",
+                          "leprint", "leprintln", "lpanic"]
+                .iter()
+                .map(|s| String::from(*s))
+                .collect(),
+            writer_macro_names: vec!(String::from("lwrite"), String::from("lwriteln")),
+            literal_macro_names: vec!(String::from("lstr"), String::from("lmark")),
+            find_msgs: None,
         }
     }
 
-    /// Returns a hashmap mapping the original strings (as used by `lformat!`)
-    /// to escaped strings. Only contains strings that are different and
-    /// must thus be handled.
-    pub fn original_strings<'a>(&'a self) -> &'a HashMap<String, String> {
+    /// Sets whether volatile header fields (e.g. `POT-Creation-Date`) should be
+    /// ignored when `write_pot_file` checks if the file needs to be rewritten.
+    ///
+    /// Default is `true`.
+    pub fn set_ignore_volatile_headers(&mut self, ignore: bool) -> &mut Self {
+        self.ignore_volatile_headers = ignore;
+        self
+    }
+
+    /// Sets the project name and version written in the `.pot` header.
+    ///
+    /// Defaults to `CARGO_PKG_NAME` and `CARGO_PKG_VERSION`, which are set when
+    /// running from `build.rs`.
+    pub fn set_project<S1, S2>(&mut self, name: S1, version: S2) -> &mut Self
+        where S1: Into<String>,
+              S2: Into<String> {
+        self.header.project_name = Some(name.into());
+        self.header.project_version = Some(version.into());
+        self
+    }
+
+    /// Sets the address (mail or URL) where bugs in messages should be reported.
+    ///
+    /// Defaults to `CARGO_PKG_REPOSITORY`.
+    pub fn set_bug_report_address<S: Into<String>>(&mut self, address: S) -> &mut Self {
+        self.header.bug_report_address = Some(address.into());
+        self
+    }
+
+    /// Sets the copyright holder written in the `.pot` header.
+    ///
+    /// Defaults to `CARGO_PKG_AUTHORS`.
+    pub fn set_copyright_holder<S: Into<String>>(&mut self, holder: S) -> &mut Self {
+        self.header.copyright_holder = Some(holder.into());
+        self
+    }
+
+    /// Sets the creation date written in the `.pot` header, in seconds since UNIX epoch.
+    ///
+    /// Defaults to the `SOURCE_DATE_EPOCH` environment variable if it is set, and
+    /// to the current time else.
+    pub fn set_creation_date(&mut self, timestamp: u64) -> &mut Self {
+        self.header.creation_date = Some(timestamp);
+        self
+    }
+
+    /// Sets reproducible builds mode.
+    ///
+    /// If set, and neither a creation date nor the `SOURCE_DATE_EPOCH` environment
+    /// variable have been set, the date is omitted instead of using the current time.
+    pub fn set_reproducible(&mut self, reproducible: bool) -> &mut Self {
+        self.header.reproducible = reproducible;
+        self
+    }
+
+    /// Sets the flag added to format strings containing arguments (e.g. `{}`),
+    /// which allows translation tools to check that translations use the
+    /// same placeholders.
+    ///
+    /// Default is `Some("rust-format")`; `None` disables it.
+    pub fn set_format_flag<S: Into<String>>(&mut self, flag: Option<S>) -> &mut Self {
+        self.format_flag = flag.map(|s| s.into());
+        self
+    }
+
+    /// Returns a hashmap mapping the message strings of the default domain to the
+    /// messages, with the locations of all their occurrences.
+    pub fn messages(&self) -> &HashMap<String, Message> {
+        &self.catalogs[DEFAULT_DOMAIN].messages
+    }
+
+    /// Returns the messages of a domain, if it exists.
+    pub fn domain_messages(&self, domain: &str) -> Option<&HashMap<String, Message>> {
+        self.catalogs.get(domain).map(|c| &c.messages)
+    }
+
+    /// Returns the names of all domains, in alphabetical order.
+    ///
+    /// The default domain is always included, even if it has no messages.
+    pub fn domains(&self) -> Vec<&str> {
+        let mut domains = self.catalogs.keys()
+            .map(|s| s.as_str())
+            .collect::<Vec<_>>();
+        domains.sort();
+        domains
+    }
+
+    /// Returns the domains containing this message, in alphabetical order.
+    pub fn message_domains(&self, msg: &str) -> Vec<&str> {
+        let mut domains = self.catalogs.iter()
+            .filter(|&(_, c)| c.messages.contains_key(msg))
+            .map(|(d, _)| d.as_str())
+            .collect::<Vec<_>>();
+        domains.sort();
+        domains
+    }
+
+    /// Returns the domain messages extracted from this macro are assigned to,
+    /// if any.
+    pub fn macro_domain(&self, name: &str) -> Option<&str> {
+        self.domain_macros.get(name).map(|s| s.as_str())
+    }
+
+    /// Returns true if messages extracted from some macro are assigned to
+    /// this domain (see `add_domain_macro`)
+    pub(crate) fn is_macro_domain(&self, domain: &str) -> bool {
+        self.domain_macros.values().any(|d| d == domain)
+    }
+
+    /// Assigns messages found in files under this directory to a domain.
+    ///
+    /// The directory is relative to the base directory (see `set_base_dir`)
+    /// if it isn't absolute. If several directories match a file, the first one
+    /// added wins.
+    pub fn add_domain_dir<S, P>(&mut self, domain: S, dir: P) -> &mut Self
+        where S: Into<String>,
+              P: AsRef<Path> {
+        let domain = domain.into();
+        self.catalogs.entry(domain.clone()).or_default();
+        self.domain_dirs.push((domain, dir.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Assigns messages extracted from calls to this macro to a domain, e.g.
+    /// `add_domain_macro("errors", "lformat_err")`.
+    ///
+    /// The macro is also added to the recognized macros (see `add_macro_name`).
+    /// This takes precedence over `add_domain_dir`.
+    pub fn add_domain_macro<S1, S2>(&mut self, domain: S1, name: S2) -> &mut Self
+        where S1: Into<String>,
+              S2: Into<String> {
+        let (domain, name) = (domain.into(), name.into());
+        self.catalogs.entry(domain.clone()).or_default();
+        self.add_macro_name(name.as_str());
+        self.domain_macros.insert(name, domain);
+        self
+    }
+
+    /// Returns the domain of the messages found in `file` (relative to the base
+    /// directory) according to the domain directories
+    fn dir_domain(&self, file: &Path) -> String {
+        self.domain_dirs.iter()
+            .find(|d| file.starts_with(self.relative_path(&d.1)))
+            .map(|d| d.0.clone())
+            .unwrap_or_else(|| String::from(DEFAULT_DOMAIN))
+    }
+
+    /// Sets the order in which messages are written to the `.pot` file.
+    ///
+    /// Default is `SortOrder::Source`.
+    pub fn set_sort_order(&mut self, order: SortOrder) -> &mut Self {
+        self.sort_order = order;
+        self
+    }
+
+    /// Sets the directory source paths are written relative to in the `.pot` file.
+    ///
+    /// Defaults to `CARGO_MANIFEST_DIR`, which is set when running from `build.rs`.
+    /// Files outside this directory keep the path they were given with.
+    pub fn set_base_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.base_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Sets which source references are written for each message in the `.pot` file.
+    ///
+    /// Omitting line numbers (`AddLocation::File`) reduces churn when source files change.
+    /// Default is `AddLocation::Full`.
+    pub fn set_add_location(&mut self, location: AddLocation) -> &mut Self {
+        self.add_location = location;
+        self
+    }
+
+    /// Only add messages from files matching this glob pattern (relative to the
+    /// directory given to `add_messages_from_dir`) when walking directories.
+    ///
+    /// Can be called multiple times; files matching any of the patterns are included.
+    /// By default, all `.rs` files are included.
+    pub fn add_include<S: Into<String>>(&mut self, glob: S) -> &mut Self {
+        self.include.push(glob.into());
+        self
+    }
+
+    /// Don't add messages from files or directories matching this glob pattern
+    /// (relative to the directory given to `add_messages_from_dir`), e.g. `"tests/**"`.
+    ///
+    /// Can be called multiple times. Exclude patterns take precedence over include patterns.
+    pub fn add_exclude<S: Into<String>>(&mut self, glob: S) -> &mut Self {
+        self.exclude.push(glob.into());
+        self
+    }
+
+    /// Sets whether `.gitignore` (and `.ignore`) files are honoured when walking
+    /// directories, so e.g. `target/` is skipped.
+    ///
+    /// Default is `true`.
+    pub fn set_respect_gitignore(&mut self, respect: bool) -> &mut Self {
+        self.respect_gitignore = respect;
+        self
+    }
+
+    /// Sets whether symbolic links are followed when walking directories.
+    ///
+    /// Default is `false`.
+    pub fn set_follow_links(&mut self, follow: bool) -> &mut Self {
+        self.follow_links = follow;
+        self
+    }
+
+    /// Sets whether messages in `#[cfg(test)]` modules are skipped.
+    ///
+    /// Default is `false`.
+    pub fn set_skip_test_modules(&mut self, skip: bool) -> &mut Self {
+        self.skip_test_modules = skip;
+        self
+    }
+
+    /// Sets strict mode, where adding messages fails on the first error
+    /// instead of recording it in `diagnostics` and going on.
+    ///
+    /// Default is `false`.
+    pub fn set_strict(&mut self, strict: bool) -> &mut Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Also extract messages from calls to this macro (see `add_macro_family`
+    /// for the macros generated when using `Localizer::set_macro_name`).
+    ///
+    /// By default, `lformat!` and the other macros generated by `Localizer`
+    /// (`lformat_args!`, `lprint!`, `lprintln!`, `leprint!`, `leprintln!` and
+    /// `lpanic!`, plus `lwrite!` and `lwriteln!`, see `add_writer_macro_name`,
+    /// and `lstr!` and `lmark!`, see `add_literal_macro_name`) are recognized.
+    pub fn add_macro_name<S: Into<String>>(&mut self, name: S) -> &mut Self {
+        let name = name.into();
+        if !self.macro_names.contains(&name) {
+            self.macro_names.push(name);
+            self.find_msgs = None;
+        }
+        self
+    }
+
+    /// Also extract messages from all the macros `Localizer` generates when its
+    /// format macro is renamed to `name` (see `Localizer::set_macro_name`).
+    ///
+    /// E.g. with `mylib_format`, this adds `mylib_format!`, `mylib_format_args!`,
+    /// `mylib_println!` and the other format macros, `mylib_write!` and
+    /// `mylib_writeln!` as writer macros, and `mylib_str!` and `mylib_mark!` as
+    /// literal macros.
+    pub fn add_macro_family<S: Into<String>>(&mut self, name: S) -> &mut Self {
+        let name = name.into();
+        self.add_macro_name(name.as_str());
+        self.add_macro_name(macrogen::companion_name(&name, "format_args"));
+        for &(suffix, _, writer) in COMPANIONS {
+            let companion = macrogen::companion_name(&name, suffix);
+            if writer {
+                self.add_writer_macro_name(companion);
+            } else {
+                self.add_macro_name(companion);
+            }
+        }
+        self.add_literal_macro_name(macrogen::companion_name(&name, "str"));
+        self.add_literal_macro_name(macrogen::companion_name(&name, "mark"));
+        self
+    }
+
+    /// Also extract messages from calls to this macro, whose message is the
+    /// second argument, the first one being the destination (e.g. `lwrite!(f, "foo")`).
+    pub fn add_writer_macro_name<S: Into<String>>(&mut self, name: S) -> &mut Self {
+        let name = name.into();
+        if !self.writer_macro_names.contains(&name) {
+            self.writer_macro_names.push(name);
+            self.find_msgs = None;
+        }
+        self
+    }
+
+    /// Also extract messages from calls to this macro, whose message is not a
+    /// format string (e.g. `lstr!("Use {braces}")`): it isn't flagged as
+    /// `rust-format`, and its translation isn't checked for format arguments.
+    pub fn add_literal_macro_name<S: Into<String>>(&mut self, name: S) -> &mut Self {
+        let name = name.into();
+        if !self.literal_macro_names.contains(&name) {
+            self.literal_macro_names.push(name);
+            self.find_msgs = None;
+        }
+        self
+    }
+
+    /// Returns true if this message was only extracted from macros whose
+    /// message is not a format string (see `add_literal_macro_name`)
+    pub(crate) fn is_literal_message(&self, msg: &str) -> bool {
+        let mut messages = self.catalogs.values()
+            .filter_map(|c| c.messages.get(msg))
+            .peekable();
+        messages.peek().is_some() && messages.all(|m| !m.format)
+    }
+
+    /// Returns a hashmap mapping the original strings (as written in `lformat!`
+    /// invocations) to the messages they represent. Only contains strings that
+    /// use other escape sequences than the ones generated code uses (e.g.
+    /// `\u{e9}` instead of `é`, or a backslash at end of line), and must thus
+    /// be handled.
+    pub fn original_strings(&self) -> &HashMap<String, String> {
         &self.orig_strings
     }
 
+    /// Returns a hashmap mapping the string literals of `concat!` invocations
+    /// used as first argument of `lformat!` (e.g. `lformat!(concat!("foo", "bar"))`)
+    /// to the concatenated message.
+    pub fn concat_strings(&self) -> &HashMap<Vec<String>, String> {
+        &self.concat_strings
+    }
+
+    /// Returns a hashmap mapping the raw string literals used as first argument
+    /// of `lformat!`, as written in source code (e.g. `r#"Say "{}""#`), to the
+    /// messages they represent.
+    pub fn raw_strings(&self) -> &HashMap<String, String> {
+        &self.raw_strings
+    }
+
+    /// Returns the diagnostics (problems that didn't stop the extraction)
+    /// encountered while adding messages.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Returns the diagnostics encountered so far, and clears them
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        mem::take(&mut self.diagnostics)
+    }
+
+    /// Records a diagnostic, or returns it as an error of this kind, showing
+    /// the offending source line, if it is an error and strict mode is set.
+    fn report(&mut self, kind: ErrorKind, diagnostic: Diagnostic, snippet: &str) -> Result<()> {
+        if self.strict && diagnostic.severity == Severity::Error {
+            let mut error = Error::with_kind(kind, diagnostic.reason).with_file(diagnostic.file);
+            if diagnostic.line > 0 {
+                error = error.with_position(diagnostic.line, diagnostic.column)
+                    .with_snippet(snippet);
+            }
+            Err(error)
+        } else {
+            self.diagnostics.push(diagnostic);
+            Ok(())
+        }
+    }
+
+    /// Records an error that concerns a whole file as a diagnostic, or returns
+    /// it if strict mode is set.
+    fn report_error(&mut self, error: Error) -> Result<()> {
+        if self.strict {
+            return Err(error);
+        }
+        let reason = match error.source() {
+            Some(source) => format!("{}: {}", error.message(), source),
+            None => error.message().to_owned(),
+        };
+        self.diagnostics.push(Diagnostic::new(Severity::Error, error.file().unwrap_or(""), 0, 0, reason));
+        Ok(())
+    }
+
     /// Add all the messages contained in a source file
+    ///
+    /// Returns an error if the file can't be read. Unless strict mode is set,
+    /// problems in its content (such as a string that can't be parsed) don't
+    /// stop the extraction, but are recorded in `diagnostics`.
     pub fn add_messages_from_file<P: AsRef<Path>>(&mut self, file: P) -> Result<()> {
-        lazy_static! {
-            static ref REMOVE_COMMS: Regex = Regex::new(r#"//[^\n]*"#).unwrap();
-            static ref FIND_MSGS: Regex = Regex::new(r#"lformat!\("#).unwrap();
-        }
-        
-        let filename =  format!("{}", file.as_ref().display());
-        let mut f = try!(File::open(file)
-                         .map_err(|e| Error::parse(format!("could not open file {}: {}",
-                                                           &filename,
-                                                           e))));
+        let (relative, content) = self.read_file(file.as_ref())?;
+        self.add_messages_from_str(&relative, &content)
+    }
+
+    /// Reads a source file, returning its path relative to the base directory
+    /// and its content
+    fn read_file(&self, file: &Path) -> Result<(PathBuf, String)> {
+        let relative = self.relative_path(file);
+        let filename = normalize_path(&relative);
+        let mut f = File::open(file)
+            .map_err(|e| Error::io("could not open file", e).with_file(filename.as_str()))?;
         let mut content = String::new();
-        try!(f.read_to_string(&mut content)
-            .map_err(|e| Error::parse(format!("could not read file {}: {}",
-                                              &filename,
-                                              e))));
-        content = REMOVE_COMMS.replace_all(&content, "").into_owned();
-
-        for caps in FIND_MSGS.captures_iter(&content) {
-            let pos = caps.get(0).unwrap().end();
-            let line = 1 + &content[..pos].bytes().filter(|b| b == &b'\n').count();
-            
-            let bytes = content[pos..].as_bytes();
-            let orig_msg: String = try!(find_string(bytes)
-                                   .map_err(|_| Error::parse(format!("{}:{}: could not parse as string",
-                                                                     &filename,
-                                                                     line))));
-            let msg = escape_string(orig_msg.as_str()).into_owned();
-            if msg != orig_msg {
-                self.orig_strings.insert(orig_msg, msg.clone());
-            }
-            
-            if self.messages.contains_key(msg.as_str()) {
-                self.messages.get_mut(&msg).unwrap().add_source(filename.as_str(), line);
+        f.read_to_string(&mut content)
+            .map_err(|e| if e.kind() == io::ErrorKind::InvalidData {
+                Error::encoding("file is not valid UTF-8").with_source(e)
+            } else {
+                Error::io("could not read file", e)
+            }.with_file(filename.as_str()))?;
+        Ok((relative, content))
+    }
+
+    /// Add messages from the content of a file, `relative` being its path relative
+    /// to the root directory
+    pub(crate) fn add_messages_from_str(&mut self, relative: &Path, content: &str) -> Result<()> {
+        let find_msgs = match self.find_msgs {
+            Some(ref find_msgs) => find_msgs.clone(),
+            None => {
+                let find_msgs = Regex::new(&format!(r#"\b({})!\s*\("#,
+                                                    self.macro_names.iter()
+                                                    .chain(self.writer_macro_names.iter())
+                                                    .chain(self.literal_macro_names.iter())
+                                                    .map(|s| regex::escape(s))
+                                                    .collect::<Vec<_>>()
+                                                    .join("|")))
+                    .map_err(|e| Error::new(format!("invalid macro name: {}", e)))?;
+                self.find_msgs = Some(find_msgs.clone());
+                find_msgs
+            },
+        };
+
+        let filename = normalize_path(relative);
+        // Replace comments by spaces rather than removing them, so byte offsets are
+        // kept (but not columns, since each byte of a character becomes a space)
+        let source = content;
+        let mut content = blank_comments(content);
+        if self.skip_test_modules {
+            content = blank_test_modules(&content);
+        }
+
+        let dir_domain = self.dir_domain(relative);
+        for caps in find_msgs.captures_iter(&content) {
+            let mut pos = skip_whitespace(&content, caps.get(0).unwrap().end());
+            if self.writer_macro_names.iter().any(|name| name == &caps[1]) {
+                match skip_argument(&content, pos) {
+                    Some(next) => pos = skip_whitespace(&content, next),
+                    None => continue,
+                }
+            }
+            if content[pos..].starts_with(')') {
+                // No message, e.g. `lprintln!()`
+                continue;
+            }
+            let (argument, begin, end) = match parse_argument(&content, pos, &caps[1]) {
+                Ok(res) => res,
+                Err((pos, severity, reason)) => {
+                    let location = location_of(source, &filename, pos, pos);
+                    self.report(ErrorKind::Parse,
+                                Diagnostic::new(severity, filename.as_str(),
+                                                location.line, location.column,
+                                                reason),
+                                line_at(source, pos))?;
+                    continue;
+                },
+            };
+            let location = location_of(source, &filename, begin, end);
+            let msg = match argument {
+                Argument::Literal(ref orig_msg) => unescape_rust(orig_msg),
+                Argument::Concat(ref parts) => parts.iter()
+                    .map(|s| unescape_rust(s))
+                    .collect::<Result<Vec<_>>>()
+                    .map(|v| v.concat()),
+                Argument::Raw(_, ref text) => Ok(text.clone()),
+            };
+            let msg = match msg {
+                Ok(msg) => msg,
+                Err(e) => {
+                    self.report(e.kind(),
+                                Diagnostic::new(Severity::Error, filename.as_str(),
+                                                location.line, location.column,
+                                                format!("invalid string literal: {}", e)),
+                                line_at(source, begin))?;
+                    continue;
+                },
+            };
+            match argument {
+                Argument::Literal(orig_msg) => if escape_rust(&msg) != orig_msg {
+                    self.orig_strings.insert(orig_msg, msg.clone());
+                },
+                Argument::Concat(parts) => {
+                    self.concat_strings.insert(parts, msg.clone());
+                },
+                Argument::Raw(token, _) => {
+                    self.raw_strings.insert(token, msg.clone());
+                },
+            }
+
+            let domain = self.domain_macros.get(&caps[1])
+                .cloned()
+                .unwrap_or_else(|| dir_domain.clone());
+            let catalog = self.catalogs.entry(domain).or_default();
+            let format = !self.literal_macro_names.iter().any(|name| name == &caps[1]);
+            if let Some(message) = catalog.messages.get_mut(msg.as_str()) {
+                message.add_location(location);
+                message.format |= format;
             } else {
                 let mut message = Message::new(msg.as_str());
-                message.add_source(filename.as_str(), line);
-                self.messages.insert(msg, message);
+                message.add_location(location);
+                message.format = format;
+                catalog.order.push(msg.clone());
+                catalog.messages.insert(msg, message);
             }
         }
 
@@ -104,55 +655,859 @@ impl Extractor {
     }
 
     /// Add messages from all `.rs` files contained in a directory
-    /// (walks through subdirectories)
+    /// (walks through subdirectories, in alphabetical order)
+    ///
+    /// Files are filtered according to the include and exclude patterns and,
+    /// unless disabled, to `.gitignore` files.
     pub fn add_messages_from_dir<P: AsRef<Path>>(&mut self, dir: P) -> Result<()> {
-        let filtered =  WalkDir::new(dir)
-            .into_iter()
-            .filter_map(|e| e.ok())
-            .map(|e| e.path()
-                 .to_string_lossy()
-                 .into_owned())
-            .filter(|s| s.ends_with(".rs"));
+        let dir = dir.as_ref();
+        let mut overrides = OverrideBuilder::new(dir);
+        let globs = self.include.iter()
+            .cloned()
+            .chain(self.exclude.iter().map(|s| format!("!{}", s)));
+        for glob in globs {
+            overrides.add(&glob)
+                .map_err(|e| Error::new(format!("invalid glob pattern {}: {}", glob, e)))?;
+        }
+        let overrides = overrides.build()
+            .map_err(|e| Error::new(format!("invalid glob patterns: {}", e)))?;
+
+        let entries: Vec<_> = WalkBuilder::new(dir)
+            .standard_filters(false)
+            .git_ignore(self.respect_gitignore)
+            .git_exclude(self.respect_gitignore)
+            .ignore(self.respect_gitignore)
+            .require_git(false)
+            .follow_links(self.follow_links)
+            .overrides(overrides)
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .build()
+            .collect();
+        let mut filtered = vec!();
+        for entry in entries {
+            match entry {
+                Ok(entry) => filtered.push(entry),
+                Err(e) => {
+                    let dir = normalize_path(&self.relative_path(dir));
+                    self.report_error(Error::with_kind(ErrorKind::Io, "error walking directory")
+                                      .with_source(e)
+                                      .with_file(dir))?;
+                }
+            }
+        }
+        let filtered = filtered.into_iter()
+            .filter(|e| e.file_type().map(|t| t.is_file()).unwrap_or(false))
+            .map(|e| e.into_path())
+            .filter(|p| p.extension().map(|e| e == "rs").unwrap_or(false));
         for filename in filtered {
-            try!(self.add_messages_from_file(&filename));
+            match self.read_file(&filename) {
+                Ok((relative, content)) => self.add_messages_from_str(&relative, &content)?,
+                Err(e) => self.report_error(e)?,
+            }
         }
 
         Ok(())
     }
 
-    /// Generate a pot-like file from the strings extracted from all files (if any)
+    /// Generate a pot-like file from the strings of the default domain extracted
+    /// from all files (if any)
     pub fn generate_pot_file(&self) -> String {
-        let mut output = String::from(POT_HEADER);
-        let mut values = self.messages
-            .values()
-            .collect::<Vec<_>>();
-        values.sort();
+        self.generate_domain_pot_file(DEFAULT_DOMAIN)
+    }
+
+    /// Generate a pot-like file from the strings of a domain (the file only
+    /// contains the header if the domain doesn't exist)
+    pub fn generate_domain_pot_file(&self, domain: &str) -> String {
+        let mut output = self.header.render();
+        let mut values = match self.catalogs.get(domain) {
+            Some(catalog) => catalog.order
+                .iter()
+                .map(|key| &catalog.messages[key])
+                .collect::<Vec<_>>(),
+            None => vec!(),
+        };
+        match self.sort_order {
+            SortOrder::Source => values.sort(),
+            SortOrder::Msgid => values.sort_by(|a, b| a.msg.cmp(&b.msg)),
+            SortOrder::FirstOccurrence => (),
+        }
         for value in values {
-            output.push_str(&format!("{}", value));
+            match self.format_flag {
+                Some(ref flag) if value.format && has_arguments(&value.msg) => {
+                    let mut value = value.clone();
+                    value.add_flag(flag.as_str());
+                    value.write_entry(&mut output, self.add_location).unwrap();
+                },
+                _ => value.write_entry(&mut output, self.add_location).unwrap(),
+            }
         }
         output
     }
 
+    /// Returns the path of `file` relative to the base directory, if possible
+    fn relative_path(&self, file: &Path) -> PathBuf {
+        if let Some(ref base) = self.base_dir {
+            if let Ok(path) = file.strip_prefix(base) {
+                return path.to_path_buf();
+            }
+            if let (Ok(file), Ok(base)) = (file.canonicalize(), base.canonicalize()) {
+                if let Ok(path) = file.strip_prefix(base) {
+                    return path.to_path_buf();
+                }
+            }
+        }
+        file.to_path_buf()
+    }
+
     /// Write a pot-like file to specified location
-    pub fn write_pot_file(&mut self, file: &str) -> Result<()> {
-        let mut f = try!(File::create(file).map_err(|e| Error::new(format!("Could not create file {}: {}",
-                                                                              file, e))));
-        let content = self.generate_pot_file();
-        try!(f.write_all(content.as_bytes())
-             .map_err(|e| Error::new(format!("Could not write to file {}: {}",
-                                             file, e))));
+    ///
+    /// The file is not touched if it already has the same content (ignoring
+    /// volatile header fields if `set_ignore_volatile_headers` was set), so
+    /// calling this from `build.rs` doesn't trigger needless rebuilds.
+    /// Else, it is replaced atomically.
+    pub fn write_pot_file<P: AsRef<Path>>(&mut self, file: P) -> Result<()> {
+        self.write_domain_pot_file(DEFAULT_DOMAIN, file)
+    }
+
+    /// Write a pot-like file containing the messages of a domain to specified
+    /// location, in the same way as `write_pot_file`
+    pub fn write_domain_pot_file<P: AsRef<Path>>(&mut self, domain: &str, file: P) -> Result<()> {
+        let file = file.as_ref();
+        let content = self.generate_domain_pot_file(domain);
+        if let Ok(old_content) = fs::read_to_string(file) {
+            let unchanged = if self.ignore_volatile_headers {
+                strip_volatile_headers(&old_content) == strip_volatile_headers(&content)
+            } else {
+                old_content == content
+            };
+            if unchanged {
+                return Ok(());
+            }
+        }
+        write_atomic(file, &content)
+    }
+
+    /// Write one pot-like file per domain in `dir`, named after the domain
+    /// (e.g. `default.pot`, `errors.pot`)
+    pub fn write_pot_files<P: AsRef<Path>>(&mut self, dir: P) -> Result<()> {
+        let domains = self.domains()
+            .into_iter()
+            .map(String::from)
+            .collect::<Vec<_>>();
+        for domain in domains {
+            self.write_domain_pot_file(&domain, dir.as_ref().join(format!("{}.pot", domain)))?;
+        }
         Ok(())
     }
 }
 
-const POT_HEADER: &'static str = r#"# SOME DESCRIPTIVE TITLE
-# Copyright (C) YEAR THE PACKAGE'S COPYRIGHT HOLDER
-# LICENSE
-# AUTHOR <EMAIL@ADDRESS>, YEAR.
-#
-#, fuzzy
-msgid ""
-msgstr ""
-"Content-Type: text/plain; charset=UTF-8\n"
+/// First argument of a call to `lformat!` (or another extracted macro)
+enum Argument {
+    /// A string literal (content between the quotes, as written in source code)
+    Literal(String),
+    /// A `concat!` of string literals
+    Concat(Vec<String>),
+    /// A raw string literal (as written in source code, and its content)
+    Raw(String, String),
+}
+
+/// Parses the first argument of a call to `lformat!`, starting at `pos`
+///
+/// Returns the argument and the byte offsets of its beginning and end, or the
+/// position, severity and description of the problem.
+fn parse_argument(content: &str, pos: usize, name: &str)
+                  -> ::std::result::Result<(Argument, usize, usize), (usize, Severity, String)> {
+    lazy_static! {
+        static ref CONCAT: Regex = Regex::new(r#"^concat!\s*\("#).unwrap();
+    }
+
+    let pos = skip_whitespace(content, pos);
+    if content[pos..].starts_with('"') {
+        let (literal, end) = parse_literal(content, pos)?;
+        return Ok((Argument::Literal(literal), pos, end));
+    }
+    match find_raw_string_pos(&content[pos..]) {
+        Some(Ok((begin, end, after))) => {
+            return Ok((Argument::Raw(content[pos..pos + after].to_owned(),
+                                     content[pos + begin..pos + end].to_owned()),
+                       pos, pos + after));
+        },
+        Some(Err(_)) => return Err((pos, Severity::Error, String::from("unterminated raw string"))),
+        None => (),
+    }
+    if let Some(m) = CONCAT.find(&content[pos..]) {
+        let mut parts = vec!();
+        let mut i = pos + m.end();
+        loop {
+            i = skip_whitespace(content, i);
+            if content[i..].starts_with(')') && !parts.is_empty() {
+                return Ok((Argument::Concat(parts), pos, i + 1));
+            }
+            if find_raw_string_pos(&content[i..]).is_some() {
+                return Err((i, Severity::Warning,
+                            String::from("raw string literals are not supported in concat!, \
+                                          message not extracted")));
+            }
+            if !content[i..].starts_with('"') {
+                return Err((i, Severity::Warning,
+                            format!("argument of concat! is not a string literal: '{}', \
+                                     message not extracted", token_at(content, i))));
+            }
+            let (literal, end) = parse_literal(content, i)?;
+            parts.push(literal);
+            i = skip_whitespace(content, end);
+            if content[i..].starts_with(',') {
+                i += 1;
+            } else if !content[i..].starts_with(')') {
+                return Err((i, Severity::Error,
+                            format!("expected ',' or ')' in concat!, found '{}'", token_at(content, i))));
+            }
+        }
+    }
+    Err((pos, Severity::Warning,
+         format!("first argument of {}! is not a string literal: '{}', \
+                  message not extracted", name, token_at(content, pos))))
+}
+
+/// Parses the string literal starting at `pos`, returning its content and the
+/// byte offset after the closing quote
+fn parse_literal(content: &str, pos: usize)
+                 -> ::std::result::Result<(String, usize), (usize, Severity, String)> {
+    find_string_pos(&content[pos..])
+        .map(|(begin, end)| (content[pos + begin..pos + end].to_owned(), pos + end + 1))
+        .map_err(|_| (pos, Severity::Error, String::from("could not parse as string")))
+}
+
+/// Skips the expression starting at `pos`, returning the position after the
+/// comma that ends it, or `None` if it isn't followed by another argument
+///
+/// Used to find the message of `lwrite!`-like macros.
+fn skip_argument(content: &str, pos: usize) -> Option<usize> {
+    let bytes = content.as_bytes();
+    let mut depth = 0;
+    let mut i = pos;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'(' | b'[' | b'{' => depth += 1,
+            b')' | b']' | b'}' if depth == 0 => return None,
+            b')' | b']' | b'}' => depth -= 1,
+            b',' if depth == 0 => return Some(i + 1),
+            b'r' => match raw_string_at(content, i) {
+                Some(Ok(end)) => {
+                    i = end;
+                    continue;
+                },
+                Some(Err(_)) => return None,
+                None => (),
+            },
+            b'"' => match parse_literal(content, i) {
+                Ok((_, end)) => {
+                    i = end;
+                    continue;
+                },
+                Err(_) => return None,
+            },
+            // Character literal (as opposed to a lifetime)
+            b'\'' if bytes.get(i + 1) == Some(&b'\\') => {
+                i += content[i + 2..].find('\'').map(|n| n + 3)?;
+                continue;
+            },
+            b'\'' if bytes.get(i + 2) == Some(&b'\'') => {
+                i += 3;
+                continue;
+            },
+            _ => (),
+        }
+        i += 1;
+    }
+    None
+}
+
+/// If a raw string literal (or raw byte string) starts at `pos`, which holds
+/// an `r`, returns the byte offset after its end
+///
+/// An `r` ending an identifier (e.g. `bar"`, which isn't valid Rust anyway) is
+/// not a raw string.
+fn raw_string_at(content: &str, pos: usize) -> Option<Result<usize>> {
+    let is_ident = |i: usize| {
+        let b = content.as_bytes()[i];
+        b.is_ascii_alphanumeric() || b == b'_' || b >= 0x80
+    };
+    let prefixed = pos > 0 && is_ident(pos - 1);
+    let byte_prefixed = pos > 0 && content.as_bytes()[pos - 1] == b'b'
+        && (pos == 1 || !is_ident(pos - 2));
+    if prefixed && !byte_prefixed {
+        return None;
+    }
+    find_raw_string_pos(&content[pos..])
+        .map(|res| res.map(|(_, _, after)| pos + after))
+}
+
+/// Returns the position of the first non-whitespace character from `pos`
+fn skip_whitespace(content: &str, pos: usize) -> usize {
+    pos + content[pos..].len() - content[pos..].trim_start().len()
+}
+
+/// Returns the token starting at `pos`, for error messages
+fn token_at(content: &str, pos: usize) -> &str {
+    content[pos..].split(|c: char| c == ',' || c == ')' || c.is_whitespace())
+        .next()
+        .unwrap_or("")
+}
+
+/// Replace comments by spaces (keeping newlines, so positions in the file are preserved)
+///
+/// String and character literals are skipped, so `//` in `"http://..."` isn't
+/// mistaken for a comment.
+fn blank_comments(content: &str) -> String {
+    let mut bytes = content.as_bytes().to_vec();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => match find_string_pos(&content[i..]) {
+                Ok((_, end)) => i += end,
+                // Unterminated string: nothing after it can be a comment
+                Err(_) => break,
+            },
+            b'r' => match raw_string_at(content, i) {
+                Some(Ok(end)) => {
+                    i = end;
+                    continue;
+                },
+                Some(Err(_)) => break,
+                None => (),
+            },
+            b'\'' if bytes.get(i + 1) == Some(&b'\\') => {
+                // Escaped character literal, e.g. '\n' or '\''
+                if let Some(n) = content.get(i + 3..).and_then(|s| s.find('\'')) {
+                    i += n + 3;
+                }
+            },
+            b'\'' => {
+                // Character literal, as opposed to a lifetime
+                if let Some(c) = content[i + 1..].chars().next() {
+                    if content[i + 1 + c.len_utf8()..].starts_with('\'') {
+                        i += c.len_utf8() + 1;
+                    }
+                }
+            },
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    bytes[i] = b' ';
+                    i += 1;
+                }
+                continue;
+            },
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                // Block comments can be nested
+                let mut depth = 0;
+                while i < bytes.len() {
+                    if bytes[i] == b'/' && bytes.get(i + 1) == Some(&b'*') {
+                        depth += 1;
+                        bytes[i] = b' ';
+                        i += 1;
+                    } else if bytes[i] == b'*' && bytes.get(i + 1) == Some(&b'/') {
+                        depth -= 1;
+                        bytes[i] = b' ';
+                        i += 1;
+                    }
+                    if bytes[i] != b'\n' {
+                        bytes[i] = b' ';
+                    }
+                    i += 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                continue;
+            },
+            _ => (),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Replace the content of `#[cfg(test)]` modules by spaces (keeping newlines,
+/// so positions in the file are preserved)
+///
+/// Braces are naively counted, which should be good enough since comments have
+/// already been removed and braces in format strings are usually balanced.
+fn blank_test_modules(content: &str) -> String {
+    lazy_static! {
+        static ref TEST_MODULE: Regex =
+            Regex::new(r#"#\[cfg\(test\)\]\s*(pub(\([^)]*\))?\s+)?mod\s+\w+\s*\{"#).unwrap();
+    }
+
+    let mut bytes = content.as_bytes().to_vec();
+    for m in TEST_MODULE.find_iter(content) {
+        let mut depth = 0;
+        for b in &mut bytes[m.start()..] {
+            match *b {
+                b'{' => depth += 1,
+                b'}' => depth -= 1,
+                _ => (),
+            }
+            let end = depth == 0 && *b == b'}';
+            if *b != b'\n' {
+                *b = b' ';
+            }
+            if end {
+                break;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Returns the location of the string literal between the `start` and `end` byte offsets
+fn location_of(content: &str, file: &str, start: usize, end: usize) -> Location {
+    let line_start = content[..start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    Location {
+        file: file.to_owned(),
+        line: 1 + content[..start].bytes().filter(|b| b == &b'\n').count(),
+        column: 1 + content[line_start..start].chars().count(),
+        start,
+        end,
+    }
+}
+
+/// Returns the line containing byte `pos`, without its line ending
+fn line_at(content: &str, pos: usize) -> &str {
+    let start = content[..pos].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let end = content[pos..].find('\n').map(|i| pos + i).unwrap_or(content.len());
+    content[start..end].trim_end_matches('\r')
+}
+
+/// Returns the path as a string, using `/` as separator whatever the platform
+fn normalize_path(path: &Path) -> String {
+    path.components()
+        .map(|c| match c {
+            Component::RootDir => String::new(),
+            _ => c.as_os_str().to_string_lossy().into_owned(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Header fields whose value changes at each generation
+const VOLATILE_HEADERS: &[&str] = &["POT-Creation-Date:"];
+
+/// Header comments containing the current year
+const DATED_COMMENTS: &[&str] = &["# Copyright (C) ", "# FIRST AUTHOR "];
+
+/// Remove the lines containing volatile header fields, and the years in
+/// header comments
+fn strip_volatile_headers(s: &str) -> String {
+    lazy_static! {
+        static ref YEAR: Regex = Regex::new(r"\b[0-9]{4}\b").unwrap();
+    }
+    s.lines()
+        .filter(|line| !VOLATILE_HEADERS.iter()
+                .any(|h| line.trim_start_matches('"').starts_with(h)))
+        .map(|line| if DATED_COMMENTS.iter().any(|c| line.starts_with(c)) {
+            YEAR.replace_all(line, "YEAR").into_owned()
+        } else {
+            line.to_owned()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+
+#[test]
+fn write_pot_file_unchanged() {
+    let dir = TempDir::new("write-pot-unchanged");
+    let file = dir.join("default.pot");
+    let mut extractor = Extractor::new();
+    extractor.write_pot_file(&file).unwrap();
+    let modified = fs::metadata(&file).unwrap().modified().unwrap();
+    ::std::thread::sleep(::std::time::Duration::from_millis(20));
+    extractor.write_pot_file(&file).unwrap();
+    assert_eq!(fs::metadata(&file).unwrap().modified().unwrap(), modified);
+    assert_eq!(fs::read_dir(&*dir).unwrap().count(), 1);
+}
+
+#[test]
+fn strip_volatile_headers_1() {
+    let a = "msgid \"\"\nmsgstr \"\"\n\"POT-Creation-Date: 2017-01-01 00:00+0000\\n\"\n";
+    let b = "msgid \"\"\nmsgstr \"\"\n\"POT-Creation-Date: 2018-01-01 00:00+0000\\n\"\n";
+    assert_eq!(strip_volatile_headers(a), strip_volatile_headers(b));
+    let a = "# Copyright (C) 2017 Jane Doe\n# FIRST AUTHOR <EMAIL@ADDRESS>, 2017.\n";
+    let b = "# Copyright (C) 2018 Jane Doe\n# FIRST AUTHOR <EMAIL@ADDRESS>, 2018.\n";
+    let c = "# Copyright (C) 2018 John Doe\n# FIRST AUTHOR <EMAIL@ADDRESS>, 2018.\n";
+    assert_eq!(strip_volatile_headers(a), strip_volatile_headers(b));
+    assert!(strip_volatile_headers(b) != strip_volatile_headers(c));
+}
+
+#[test]
+fn normalize_path_1() {
+    assert_eq!(&normalize_path(Path::new("src/lib.rs")), "src/lib.rs");
+    assert_eq!(&normalize_path(Path::new("./src//foo/lib.rs")), "./src/foo/lib.rs");
+    assert_eq!(&normalize_path(Path::new("/src/lib.rs")), "/src/lib.rs");
+}
+
+#[test]
+fn sort_order() {
+    let mut extractor = Extractor::new();
+    for &(msg, file, line) in &[("b", "src/b.rs", 1), ("c", "src/a.rs", 2), ("a", "src/a.rs", 3)] {
+        let mut message = Message::new(msg);
+        message.add_source(file, line);
+        let catalog = extractor.catalogs.get_mut(DEFAULT_DOMAIN).unwrap();
+        catalog.order.push(String::from(msg));
+        catalog.messages.insert(String::from(msg), message);
+    }
+    let msgids = |extractor: &Extractor| extractor.generate_pot_file()
+        .lines()
+        .filter(|l| l.starts_with("msgid \"") && *l != "msgid \"\"")
+        .map(|l| l.to_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(msgids(&extractor), vec!("msgid \"c\"", "msgid \"a\"", "msgid \"b\""));
+    extractor.set_sort_order(SortOrder::Msgid);
+    assert_eq!(msgids(&extractor), vec!("msgid \"a\"", "msgid \"b\"", "msgid \"c\""));
+    extractor.set_sort_order(SortOrder::FirstOccurrence);
+    assert_eq!(msgids(&extractor), vec!("msgid \"b\"", "msgid \"c\"", "msgid \"a\""));
+}
+
+#[test]
+fn sort_order_locations() {
+    // Locations are sorted whatever the order files are read in
+    let mut extractor = Extractor::new();
+    extractor.add_messages_from_str(Path::new("b.rs"), "lformat!(\"shared\");").unwrap();
+    extractor.add_messages_from_str(Path::new("c.rs"), "lformat!(\"c\");").unwrap();
+    extractor.add_messages_from_str(Path::new("a2.rs"), "lformat!(\"shared\");\nlformat!(\"a2\");")
+        .unwrap();
+    let pot = extractor.generate_pot_file();
+    let entries = pot.split("\n\n").skip(1).collect::<Vec<_>>();
+    assert_eq!(entries, vec!("#: a2.rs:1 b.rs:1\nmsgid \"shared\"\nmsgstr \"\"",
+                             "#: a2.rs:2\nmsgid \"a2\"\nmsgstr \"\"",
+                             "#: c.rs:1\nmsgid \"c\"\nmsgstr \"\"",
+                             ""));
+}
+
+#[test]
+fn relative_path_1() {
+    let mut extractor = Extractor::new();
+    extractor.set_base_dir("/home/alice/proj");
+    assert_eq!(extractor.relative_path(Path::new("/home/alice/proj/src/main.rs")),
+               Path::new("src/main.rs"));
+    assert_eq!(extractor.relative_path(Path::new("/home/bob/main.rs")),
+               Path::new("/home/bob/main.rs"));
+}
+
+#[test]
+fn add_messages_locations() {
+    let content = "// lformat!(\"comment\")\nfn main() {\n    let é = lformat!(\"Hello, {}\", \"world\");\n}\n";
+    let dir = TempDir::new("locations");
+    dir.write("main.rs", content);
+    let mut extractor = Extractor::new();
+    extractor.set_base_dir(&dir);
+    extractor.add_messages_from_file(dir.join("main.rs")).unwrap();
+
+    assert_eq!(extractor.messages().len(), 1);
+    let locations = extractor.messages()["Hello, {}"].locations();
+    let location = locations[0];
+    assert_eq!(&location.file, "main.rs");
+    assert_eq!((location.line, location.column), (3, 22));
+    assert_eq!(&content[location.start..location.end], "\"Hello, {}\"");
+}
+
+#[test]
+fn add_messages_locations_after_comment() {
+    // Columns count the characters of comments, not the bytes
+    let mut extractor = Extractor::new();
+    extractor.add_messages_from_str(Path::new("a.rs"), "/* éé */ lformat!(\"x\"); // ü\n  /* ü */ lformat!(y);")
+        .unwrap();
+    let location = extractor.messages()["x"].locations()[0].clone();
+    assert_eq!((location.line, location.column, location.start), (1, 19, 20));
+    assert_eq!((extractor.diagnostics()[0].line, extractor.diagnostics()[0].column), (2, 20));
+}
+
+#[test]
+fn blank_test_modules_1() {
+    let s = "lformat!(\"foo\");
+#[cfg(test)]
+mod tests {
+    fn bar() { lformat!(\"bar\"); }
+}
+lformat!(\"baz\");";
+    let res = blank_test_modules(s);
+    assert_eq!(res.len(), s.len());
+    assert_eq!(res.lines().count(), s.lines().count());
+    assert!(res.contains("foo"));
+    assert!(!res.contains("bar"));
+    assert!(res.contains("baz"));
+}
+
+#[test]
+fn add_messages_from_dir_filters() {
+    let dir = TempDir::new("dir-filters");
+    dir.write(".gitignore", "/target/\n")
+        .write("src/main.rs", "lformat!(\"main\");")
+        .write("target/gen.rs", "lformat!(\"generated\");")
+        .write("tests/test.rs", "lformat!(\"test\");");
+    let mut extractor = Extractor::new();
+    extractor.add_exclude("tests/**");
+    extractor.add_messages_from_dir(&dir).unwrap();
+    let mut msgs = extractor.messages().keys().collect::<Vec<_>>();
+    msgs.sort();
+    assert_eq!(msgs, vec!("main"));
+}
+
+#[test]
+fn add_messages_diagnostics() {
+    let dir = TempDir::new("diagnostics");
+    dir.write("a.rs", "lformat!(\"foo\");\nlformat!(\"")
+        .write("b.rs", "lformat!(\"bar\");");
+    let mut extractor = Extractor::new();
+    extractor.set_base_dir(&dir);
+    extractor.add_messages_from_dir(&dir).unwrap();
+    assert_eq!(extractor.messages().len(), 2);
+    assert_eq!(extractor.diagnostics(),
+               &[Diagnostic::new(Severity::Error, "a.rs", 2, 10, "could not parse as string")]);
+
+    let mut extractor = Extractor::new();
+    extractor.set_strict(true);
+    extractor.set_base_dir(&dir);
+    let err = extractor.add_messages_from_dir(&dir).unwrap_err();
+    assert_eq!((err.file(), err.line(), err.column()), (Some("a.rs"), Some(2), Some(10)));
+    assert_eq!((err.kind(), err.snippet()), (ErrorKind::Parse, Some("lformat!(\"")));
+
+    let mut extractor = Extractor::new();
+    extractor.set_strict(true);
+    let err = extractor.add_messages_from_str(Path::new("c.rs"), "lformat!(\"ok\");\r\n  lformat!(\"\\q\");\r\n")
+        .unwrap_err();
+    assert_eq!((err.kind(), err.line(), err.column()), (ErrorKind::Encoding, Some(2), Some(12)));
+    assert_eq!(err.snippet(), Some("  lformat!(\"\\q\");"));
+}
+
+#[test]
+fn add_messages_non_literal() {
+    let mut extractor = Extractor::new();
+    extractor.set_strict(true);
+    extractor.add_messages_from_str(Path::new("a.rs"), "let s = lformat!(msg, x);\nlformat!(\"foo\");")
+        .unwrap();
+    assert_eq!(extractor.messages().keys().collect::<Vec<_>>(), vec!("foo"));
+    let diagnostics = extractor.diagnostics();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!((diagnostics[0].severity, diagnostics[0].line, diagnostics[0].column),
+               (Severity::Warning, 1, 18));
+    assert!(diagnostics[0].reason.contains("'msg'"));
+}
+
+#[test]
+fn add_messages_concat() {
+    let mut extractor = Extractor::new();
+    extractor.add_messages_from_str(Path::new("a.rs"), "lformat!(concat!(\"Hello, \",\n    \"{}!\",), name);
+lformat!(concat!(\"foo\", env!(\"BAR\")));
+lformat!(\"Hello, {}!\", name);").unwrap();
+    assert_eq!(extractor.messages().keys().collect::<Vec<_>>(), vec!("Hello, {}!"));
+    assert_eq!(extractor.messages()["Hello, {}!"].locations().len(), 2);
+    assert_eq!(extractor.concat_strings()[&vec!(String::from("Hello, "), String::from("{}!"))],
+               "Hello, {}!");
+    assert_eq!(extractor.diagnostics().len(), 1);
+    assert_eq!(extractor.diagnostics()[0].severity, Severity::Warning);
+}
 
-"#;
+#[test]
+fn add_messages_raw_strings() {
+    let mut extractor = Extractor::new();
+    extractor.add_messages_from_str(Path::new("a.rs"), r###"lformat!(r"C:\{}", dir);
+lformat!(r#"Say "hi" // not a comment"#);
+lformat!(concat!("foo", r"bar"));
+lformat!(r#"unterminated");"###).unwrap();
+    assert_eq!(extractor.messages().len(), 2);
+    assert_eq!(extractor.raw_strings()[r#"r"C:\{}""#], r"C:\{}");
+    assert_eq!(extractor.raw_strings()[r##"r#"Say "hi" // not a comment"#"##],
+               r#"Say "hi" // not a comment"#);
+    let diagnostics = extractor.diagnostics();
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!((diagnostics[0].severity, diagnostics[0].line), (Severity::Warning, 3));
+    assert!(diagnostics[0].reason.contains("raw string"));
+    assert_eq!((diagnostics[1].severity, diagnostics[1].line), (Severity::Error, 4));
+    assert!(extractor.generate_pot_file().contains(r#"msgid "Say \"hi\" // not a comment""#));
+}
+
+#[test]
+fn add_messages_escapes() {
+    let mut extractor = Extractor::new();
+    extractor.add_messages_from_str(Path::new("a.rs"), r#"lformat!("Caf\u{e9}\tbar");
+lformat!("Café\tbar");
+lformat!("foo\
+          bar");
+lformat!("\q");"#).unwrap();
+    assert_eq!(extractor.messages().len(), 2);
+    assert_eq!(extractor.messages()["Café\tbar"].locations().len(), 2);
+    assert_eq!(extractor.original_strings()[r"Caf\u{e9}\tbar"], "Café\tbar");
+    assert_eq!(extractor.original_strings()["foo\\\n          bar"], "foobar");
+    assert_eq!(extractor.original_strings().len(), 2);
+    assert_eq!(extractor.diagnostics().len(), 1);
+    assert!(extractor.generate_pot_file().contains("msgid \"Café\\tbar\""));
+}
+
+#[test]
+fn add_messages_macro_name() {
+    let mut extractor = Extractor::new();
+    extractor.add_macro_name("mylib_format");
+    extractor.add_messages_from_str(Path::new("a.rs"),
+                                    "mylib_format!(\"foo\"); lformat!(\"bar\"); mylib_format!(x);")
+        .unwrap();
+    assert_eq!(extractor.messages().len(), 2);
+    assert!(extractor.diagnostics()[0].reason.starts_with("first argument of mylib_format!"));
+}
+
+#[test]
+fn add_messages_macro_family() {
+    let mut extractor = Extractor::new();
+    extractor.add_macro_family("mylib_format");
+    extractor.add_messages_from_str(Path::new("a.rs"), "mylib_format!(\"a {}\", x); mylib_println!(\"b\");
+mylib_writeln!(f, \"c {}\", x); mylib_format_args!(\"d\"); mylib_str!(\"e {}\"); mylib_mark!(\"f\");")
+        .unwrap();
+    let mut msgs = extractor.messages().keys().cloned().collect::<Vec<_>>();
+    msgs.sort();
+    assert_eq!(msgs, vec!("a {}", "b", "c {}", "d", "e {}", "f"));
+    assert!(extractor.diagnostics().is_empty());
+    assert!(!extractor.is_literal_message("c {}"));
+    assert!(extractor.is_literal_message("e {}"));
+
+    let mut extractor = Extractor::new();
+    extractor.add_macro_family("tr");
+    extractor.add_messages_from_str(Path::new("a.rs"), "tr!(\"a\"); tr_eprintln!(\"b\"); tr_str!(\"c\");").unwrap();
+    assert_eq!(extractor.messages().len(), 3);
+}
+
+#[test]
+fn add_messages_macro_name_later() {
+    // Adding a macro after some files were read is taken into account
+    let mut extractor = Extractor::new();
+    extractor.add_messages_from_str(Path::new("a.rs"), "mylib_format!(\"foo\");").unwrap();
+    assert_eq!(extractor.messages().len(), 0);
+    extractor.add_macro_name("mylib_format");
+    extractor.add_messages_from_str(Path::new("b.rs"), "mylib_format!(\"foo\");").unwrap();
+    assert_eq!(extractor.messages().len(), 1);
+}
+
+#[test]
+fn add_messages_domains() {
+    let dir = TempDir::new("domains");
+    dir.write("main.rs", "lformat!(\"core\"); lformat_err!(\"oops\");")
+        .write("plugins/foo.rs", "lformat!(\"plugin\"); lformat_err!(\"oops\");");
+    let mut extractor = Extractor::new();
+    extractor.set_base_dir(&dir)
+        .add_domain_dir("plugins", "plugins")
+        .add_domain_macro("errors", "lformat_err");
+    extractor.add_messages_from_dir(&dir).unwrap();
+    assert_eq!(extractor.domains(), vec!("default", "errors", "plugins"));
+    assert_eq!(extractor.messages().keys().collect::<Vec<_>>(), vec!("core"));
+    assert_eq!(extractor.domain_messages("plugins").unwrap().keys().collect::<Vec<_>>(), vec!("plugin"));
+    assert_eq!(extractor.domain_messages("errors").unwrap()["oops"].locations().len(), 2);
+    assert_eq!(extractor.message_domains("oops"), vec!("errors"));
+    assert_eq!(extractor.macro_domain("lformat_err"), Some("errors"));
+
+    let out = dir.join("po");
+    fs::create_dir_all(&out).unwrap();
+    extractor.write_pot_files(&out).unwrap();
+    let errors = fs::read_to_string(out.join("errors.pot")).unwrap();
+    assert!(errors.contains("msgid \"oops\""));
+    assert!(!errors.contains("msgid \"core\""));
+}
+
+#[test]
+fn add_messages_print_macros() {
+    let mut extractor = Extractor::new();
+    extractor.add_messages_from_str(Path::new("a.rs"),
+                                    "lprintln!(\"foo {}\", x); lprintln!(); \
+                                     lwriteln!(f.get(',', '\\''), \"bar\"); lwriteln!(f); \
+                                     lpanic!(\"baz\");").unwrap();
+    let mut msgs = extractor.messages().keys().collect::<Vec<_>>();
+    msgs.sort();
+    assert_eq!(msgs, vec!("bar", "baz", "foo {}"));
+    assert!(extractor.diagnostics().is_empty());
+}
+
+#[test]
+fn blank_comments_1() {
+    let s = "lformat!(\"http://example.org\"); // comment
+let c = '\"'; /* block /* nested */ \"not a string\" */ lformat!(\"a\\\"b // c\");
+let l: &'static str = lformat!(\"é\"); // é";
+    let res = blank_comments(s);
+    assert_eq!(res.len(), s.len());
+    assert_eq!(res.lines().count(), s.lines().count());
+    assert!(res.contains("http://example.org"));
+    assert!(!res.contains("comment"));
+    assert!(!res.contains("nested"));
+    assert!(!res.contains("not a string"));
+    assert!(res.contains("a\\\"b // c"));
+    assert!(res.contains("\"é\");"));
+}
+
+#[test]
+fn add_messages_trailing_backslash() {
+    let mut extractor = Extractor::new();
+    extractor.add_messages_from_str(Path::new("a.rs"),
+                                    "lformat!(\"C:\\\\\"); lformat!(\"http://foo\");").unwrap();
+    assert!(extractor.messages().contains_key("C:\\"));
+    assert!(extractor.messages().contains_key("http://foo"));
+    assert!(extractor.diagnostics().is_empty());
+}
+
+/// Macro calls, literals, comments and test modules, as found in source code
+#[cfg(test)]
+const RUST_TOKENS: &[&str] = &["lformat!(", "lwriteln!(", "concat!(", "(", ")", ",", "\"", "\\",
+                               "'", "é", "//", "/*", "*/", "\n", " ", "a", "{}",
+                               "#[cfg(test)] mod tests {", "}"];
+
+#[cfg(test)]
+quickcheck! {
+    fn add_messages_arbitrary_bytes(bytes: Vec<u8>) -> bool {
+        let mut extractor = Extractor::new();
+        extractor.add_messages_from_str(Path::new("a.rs"), &String::from_utf8_lossy(&bytes)).is_ok()
+    }
+
+    fn add_messages_arbitrary_tokens(tokens: Vec<u8>) -> bool {
+        let mut extractor = Extractor::new();
+        let content = from_tokens(&tokens, RUST_TOKENS);
+        let ok = extractor.add_messages_from_str(Path::new("a.rs"), &content).is_ok();
+        extractor.set_strict(true);
+        let _ = extractor.add_messages_from_str(Path::new("a.rs"), &content);
+        ok
+    }
+}
+
+#[test]
+fn add_messages_fuzz_corpus() {
+    let mut extractor = Extractor::new();
+    extractor.add_messages_from_file(concat!(env!("CARGO_MANIFEST_DIR"),
+                                             "/fuzz/corpus/extractor/main.rs")).unwrap();
+    let mut msgs = extractor.messages().keys().cloned().collect::<Vec<_>>();
+    msgs.sort();
+    assert_eq!(msgs, vec!("\"{}\" isn't '{}'", "A message split over several lines: {}",
+                          "C:\\Users\\{}\\", "Cancel", "Error: {}", "Hello, {}!", "Lazy {}",
+                          "Open", "Save", "See http://example.org/{}", "only in tests"));
+    assert_eq!(extractor.diagnostics().len(), 1);
+}
+
+#[test]
+fn add_messages_unreadable() {
+    let dir = TempDir::new("unreadable");
+    dir.write("a.rs", "lformat!(\"foo\");");
+    fs::write(dir.join("b.rs"), b"lformat!(\"\xff\");").unwrap();
+    let mut extractor = Extractor::new();
+    extractor.set_base_dir(&dir);
+    let err = extractor.add_messages_from_file(dir.join("missing.rs")).unwrap_err();
+    assert_eq!((err.kind(), err.file(), err.line()), (ErrorKind::Io, Some("missing.rs"), None));
+    assert!(err.source().is_some());
+
+    extractor.add_messages_from_dir(&dir).unwrap();
+    assert_eq!(extractor.messages().len(), 1);
+    let diagnostics = extractor.take_diagnostics();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!((diagnostics[0].severity, diagnostics[0].file.as_str()), (Severity::Error, "b.rs"));
+    assert!(diagnostics[0].reason.starts_with("file is not valid UTF-8: "));
+    assert!(extractor.diagnostics().is_empty());
+
+    extractor.set_strict(true);
+    let err = extractor.add_messages_from_dir(&dir).unwrap_err();
+    assert_eq!((err.kind(), err.file(), err.line()), (ErrorKind::Encoding, Some("b.rs"), None));
+    assert!(err.source().is_some());
+}
@@ -2,8 +2,11 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with
 // this file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use error::{Error,Result};
-use common::find_string;
+use error::{Error, ErrorKind, Result};
+use common::find_string_pos;
+use escape::unescape_po;
+#[cfg(test)]
+use common::from_tokens;
 
 use std::collections::HashMap;
 
@@ -14,6 +17,10 @@ pub struct Lang {
     pub lang: String,
     /// The content of localization
     pub content: HashMap<String, String>,
+    /// The flags (e.g. `fuzzy`, `rust-format`) associated to a key
+    pub flags: HashMap<String, Vec<String>>,
+    /// The line (starting at 1) of the `msgstr` of a key, if read from a string
+    pub(crate) lines: HashMap<String, usize>,
 }
 
 impl Lang {
@@ -23,86 +30,113 @@ impl Lang {
         Lang {
             lang: lang.into(),
             content: HashMap::new(),
+            flags: HashMap::new(),
+            lines: HashMap::new(),
         }
     }
 
     /// Create a new Lang from a string
     ///
     /// This string should vaguely follow .po/.mo files: it can contain
-    /// comments starting by a `#`, and an entry should be of the form:
+    /// comments starting by a `#` (flags, on lines starting by `#,`, are kept),
+    /// and an entry should be of the form:
     ///
     /// ```text, no_run
     /// msgid "Initial string"
     /// msgstr "Translated string"
     /// ```
+    ///
+    /// Errors report the line and column (starting at 1) of the problem.
     pub fn new_from_str<S>(lang: S, s: &str) -> Result<Lang>
         where S: Into<String> {
         let mut lang = Self::new(lang);
-        let lines:Vec<_> = s.lines()
-            .map(|s| s.trim())
-            .collect();
+        let lines: Vec<_> = s.lines().collect();
         let mut i = 0;
+        let mut flags = vec!();
         while i < lines.len() {
-            if lines[i].is_empty() || lines[i].starts_with("#") {
+            let line = lines[i].trim();
+            if let Some(line_flags) = line.strip_prefix("#,") {
+                flags.extend(line_flags.split(',')
+                             .map(|f| f.trim())
+                             .filter(|f| !f.is_empty())
+                             .map(|f| f.to_owned()));
+                i += 1;
+                continue;
+            }
+            if line.is_empty() || line.starts_with('#') {
                 // empty line or comment, ignore
                 i += 1;
                 continue;
             }
             if let Some(begin) = lines[i].find("msgid") {
-                let end = begin + "msgid".len();
-                let mut s = &lines[i][end..];
-                let mut key = String::new();
+                let mut key = lang.parse_string(&lines, i, begin + "msgid".len())?;
                 loop {
-                    key.push_str(&try!(find_string(s.as_bytes()).map_err(|e| {
-                        Error::parse(format!("initializing lang '{}' at line {}, could not parse {} as a String: {}",
-                                             &lang.lang, i, s, e))
-                    })));
-                    if i >= lines.len() - 1 || lines[i+1].starts_with("msgstr") {
-                            break;
-                    } else if lines[i+1].starts_with('"') {
-                        i = i + 1;
-                        s = lines[i];
+                    if i + 1 >= lines.len() {
+                        return Err(lang.error(ErrorKind::Parse, &lines, i, lines[i].len(),
+                                              "expected 'msgstr' after 'msgid', found end of file"));
+                    }
+                    let next = lines[i + 1].trim_start();
+                    if next.starts_with("msgstr") {
+                        break;
+                    } else if next.starts_with('"') {
+                        i += 1;
+                        key.push_str(&lang.parse_string(&lines, i, 0)?);
                     } else {
-                        return Err(Error::parse(format!("initializing lang '{}' at line {}, found 'msgid' without matching 'msgstr on next line",
-                                                        &lang.lang,
-                                                        i)));
+                        return Err(lang.error(ErrorKind::Parse, &lines, i + 1, lines[i + 1].len() - next.len(),
+                                              format!("expected 'msgstr' or a string continuing 'msgid', found {}",
+                                                      found(next))));
                     }
                 }
+                // The loop above only stops on a line starting with `msgstr`
                 i += 1;
-                if let Some(begin) = lines[i].find("msgstr") {
-                    let end = begin + "msgstr".len();
-                    let mut s = &lines[i][end..];
-                    let mut value = String::new();
-                    loop {
-                        value.push_str(&try!(find_string(s.as_bytes()).map_err(|e| {
-                        Error::parse(format!("initializing lang '{}' at line {}, could not parse {} as a String: {}",
-                                             &lang.lang,
-                                             i,
-                                             s,
-                                             e))
-                        })));
-                        if i >= lines.len() - 1 || lines[i+1].is_empty() {
-                            break;
-                        } else {
-                            i = i + 1;
-                            s = lines[i];
-                        }
-                    }
-                    if !key.is_empty() && !value.is_empty() {
-                        lang.insert(key, value);
-                    }
-                } else {
-                    unreachable!()
+                let msgstr_line = i + 1;
+                let begin = lines[i].len() - lines[i].trim_start().len() + "msgstr".len();
+                let mut value = lang.parse_string(&lines, i, begin)?;
+                while i + 1 < lines.len() && lines[i + 1].trim_start().starts_with('"') {
+                    i += 1;
+                    value.push_str(&lang.parse_string(&lines, i, 0)?);
+                }
+                if !key.is_empty() && !flags.is_empty() {
+                    lang.flags.insert(key.clone(), flags);
+                }
+                flags = vec!();
+                if !key.is_empty() && !value.is_empty() {
+                    lang.lines.insert(key.clone(), msgstr_line);
+                    lang.insert(key, value);
                 }
                 i += 1;
             } else {
-                return Err(Error::parse(format!("initializing lang '{}' at line {}, unexected input: '{}'",
-                                                &lang.lang, i, lines[i])));
+                return Err(lang.error(ErrorKind::Parse, &lines, i, lines[i].len() - lines[i].trim_start().len(),
+                                      format!("expected 'msgid', found {}", found(line))));
             }
         }
         Ok(lang)
     }
 
+    /// Parses the string starting (after optional whitespace) at byte `pos` of line `i`
+    fn parse_string(&self, lines: &[&str], i: usize, pos: usize) -> Result<String> {
+        let line = lines[i];
+        let start = line.len() - line[pos..].trim_start().len();
+        let rest = &line[start..];
+        if !rest.starts_with('"') {
+            return Err(self.error(ErrorKind::Parse, lines, i, start,
+                                  format!("expected a string, found {}", found(rest))));
+        }
+        let (begin, end) = find_string_pos(rest)
+            .map_err(|_| self.error(ErrorKind::Parse, lines, i, line.len(),
+                                    "expected '\"' to close the string, found end of line"))?;
+        unescape_po(&rest[begin..end])
+            .map_err(|e| self.error(ErrorKind::Encoding, lines, i, start,
+                                    format!("invalid string: {}", e.message())))
+    }
+
+    /// Returns an error located at byte `pos` of line `i`
+    fn error<S: AsRef<str>>(&self, kind: ErrorKind, lines: &[&str], i: usize, pos: usize, msg: S) -> Error {
+        Error::with_kind(kind, format!("initializing lang '{}': {}", self.lang, msg.as_ref()))
+            .with_position(i + 1, lines[i][..pos].chars().count() + 1)
+            .with_snippet(lines[i])
+    }
+
     /// Insert a (key, value) pair in the HashMap containing localization strings
     ///
     /// # Arguments:
@@ -113,6 +147,32 @@ impl Lang {
               S2: Into<String> {
         self.content.insert(key.into(), value.into());
     }
+
+    /// Returns true if the translation of `key` is marked as `fuzzy`
+    pub fn is_fuzzy(&self, key: &str) -> bool {
+        self.flags.get(key)
+            .map(|flags| flags.iter().any(|f| f == "fuzzy"))
+            .unwrap_or(false)
+    }
+
+    /// Removes the translations marked as `fuzzy`
+    pub fn remove_fuzzy(&mut self) {
+        let fuzzy = self.content.keys()
+            .filter(|key| self.is_fuzzy(key))
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in fuzzy {
+            self.content.remove(&key);
+        }
+    }
+}
+
+/// Describes what was found instead of the expected input, for error messages
+fn found(s: &str) -> String {
+    match s.split_whitespace().next() {
+        Some(token) => format!("'{}'", token),
+        None => String::from("end of line"),
+    }
 }
 
 
@@ -143,8 +203,9 @@ msgstr "Une chaîne"
 msgid "Other string"
 msgstr "Autre chaîne"
 "#;
-    let lang = Lang::new_from_str("fr", s);
-    assert!(lang.is_err());
+    let err = Lang::new_from_str("fr", s).unwrap_err();
+    assert_eq!((err.line(), err.column()), (Some(2), Some(1)));
+    assert!(err.message().ends_with("expected 'msgid', found 'msgstr'"));
 }
 
 #[test]
@@ -157,8 +218,9 @@ msgid "Two consecutive msgid without msgstr"
 msgid "Other string"
 msgstr "Autre chaîne"
 "#;
-    let lang = Lang::new_from_str("fr", s);
-    assert!(lang.is_err());
+    let err = Lang::new_from_str("fr", s).unwrap_err();
+    assert_eq!((err.line(), err.column()), (Some(3), Some(1)));
+    assert!(err.message().ends_with("expected 'msgstr' or a string continuing 'msgid', found 'msgid'"));
 }
 
 #[test]
@@ -198,3 +260,123 @@ msgstr "bar"
     let lang = Lang::new_from_str("fr", s).unwrap();
     assert_eq!(lang.content.len(), 0);
 }
+
+#[test]
+fn lang_flags() {
+    let s = r#"
+#: src/main.rs:12
+#, fuzzy, rust-format
+msgid "Hello, {}"
+msgstr "Bonjour, {}"
+
+msgid "foo"
+msgstr "bar"
+"#;
+    let lang = Lang::new_from_str("fr", s).unwrap();
+    assert_eq!(lang.flags.get("Hello, {}").unwrap(), &vec!(String::from("fuzzy"),
+                                                           String::from("rust-format")));
+    assert!(!lang.flags.contains_key("foo"));
+}
+
+#[test]
+fn lang_escapes() {
+    let s = r#"
+msgid "Tab\there, \"quoted\", C:\\dir"
+msgstr "Tab\tici, \"cité\", C:\\dir"
+"#;
+    let lang = Lang::new_from_str("fr", s).unwrap();
+    assert_eq!(lang.content.get("Tab\there, \"quoted\", C:\\dir").unwrap(),
+               "Tab\tici, \"cité\", C:\\dir");
+}
+
+#[cfg(test)]
+fn error_at(s: &str) -> (Option<usize>, Option<usize>, String) {
+    let err = Lang::new_from_str("fr", s).unwrap_err();
+    (err.line(), err.column(), String::from(err.message()))
+}
+
+#[test]
+fn lang_errors() {
+    assert_eq!(error_at("\nmsgid foo\nmsgstr \"\""),
+               (Some(2), Some(7), String::from("initializing lang 'fr': expected a string, found 'foo'")));
+    assert_eq!(error_at("msgid \"foo\nmsgstr \"\""),
+               (Some(1), Some(11),
+                String::from("initializing lang 'fr': expected '\"' to close the string, found end of line")));
+    assert_eq!(error_at("msgid \"é\"\n  foo"),
+               (Some(2), Some(3),
+                String::from("initializing lang 'fr': expected 'msgstr' or a string continuing 'msgid', found 'foo'")));
+    assert_eq!(error_at("msgid \"é\""),
+               (Some(1), Some(10),
+                String::from("initializing lang 'fr': expected 'msgstr' after 'msgid', found end of file")));
+    assert_eq!(error_at("msgid \"a\"\nmsgstr"),
+               (Some(2), Some(7), String::from("initializing lang 'fr': expected a string, found end of line")));
+    assert_eq!(error_at("msgid \"a\"\nmsgstr \"\"\n\t\"b"),
+               (Some(3), Some(4),
+                String::from("initializing lang 'fr': expected '\"' to close the string, found end of line")));
+    assert_eq!(error_at("\n\n  bonjour \"\""),
+               (Some(3), Some(3), String::from("initializing lang 'fr': expected 'msgid', found 'bonjour'")));
+
+    let err = Lang::new_from_str("fr", "msgid \"a\\q\"\nmsgstr \"\"").unwrap_err();
+    assert_eq!((err.kind(), err.line(), err.column()), (ErrorKind::Encoding, Some(1), Some(7)));
+    assert_eq!(err.message(), "initializing lang 'fr': invalid string: unknown escape sequence '\\q'");
+    assert_eq!(err.snippet(), Some("msgid \"a\\q\""));
+}
+
+#[test]
+fn lang_byte_escapes() {
+    let lang = Lang::new_from_str("fr", "msgid \"Cafe\"\nmsgstr \"Caf\\xc3\\xa9 \\342\\202\\254\"").unwrap();
+    assert_eq!(lang.content["Cafe"], "Café €");
+
+    let err = Lang::new_from_str("fr", "msgid \"Cafe\"\nmsgstr \"Caf\\xe9\"").unwrap_err();
+    assert_eq!((err.kind(), err.line()), (ErrorKind::Encoding, Some(2)));
+}
+
+#[test]
+fn lang_trailing_backslash() {
+    let s = r#"
+msgid "C:\\"
+msgstr "C:\\"
+
+msgid "a\\"
+"b"
+msgstr "\\"
+"#;
+    let lang = Lang::new_from_str("fr", s).unwrap();
+    assert_eq!(lang.content.get("C:\\").unwrap(), "C:\\");
+    assert_eq!(lang.content.get("a\\b").unwrap(), "\\");
+}
+
+/// Keywords, comments and string pieces of `.po` files
+#[cfg(test)]
+const PO_TOKENS: &[&str] = &["msgid ", "msgstr ", "msgctxt ", "#, fuzzy", "# ", "\"", "\\",
+                             "\\\"", "\\n", "é", "{}", "\n", " ", "\t", "a", "#,", "msgid"];
+
+#[cfg(test)]
+quickcheck! {
+    fn lang_arbitrary_bytes(bytes: Vec<u8>) -> bool {
+        let _ = Lang::new_from_str("fr", &String::from_utf8_lossy(&bytes));
+        true
+    }
+
+    fn lang_arbitrary_tokens(tokens: Vec<u8>) -> bool {
+        match Lang::new_from_str("fr", &from_tokens(&tokens, PO_TOKENS)) {
+            Ok(_) => true,
+            Err(e) => e.line().is_some(),
+        }
+    }
+}
+
+#[test]
+fn lang_fuzz_corpus() {
+    // Only the seed files: the fuzzer also writes (mostly invalid) inputs to this directory
+    let dir = ::std::path::Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/fuzz/corpus/lang"));
+    for name in &["crlf.po", "de.po", "fr.po", "gettext-features.po", "ja.po"] {
+        let content = ::std::fs::read_to_string(dir.join(name)).unwrap();
+        let res = Lang::new_from_str("fr", &content);
+        if *name == "gettext-features.po" {
+            assert_eq!(res.unwrap_err().line(), Some(8));
+        } else {
+            assert!(res.is_ok(), "{}: {}", name, res.unwrap_err());
+        }
+    }
+}
@@ -32,6 +32,10 @@
 //!     // Generate a `lang/default.pot` containing strings used to call `lformat!`
 //!     let mut extractor = Extractor::new();
 //!     extractor.add_messages_from_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/src")).unwrap();
+//!     // Report problems that didn't stop the extraction as Cargo warnings
+//!     for diagnostic in extractor.diagnostics() {
+//!         println!("cargo:warning={}", diagnostic);
+//!     }
 //!     extractor.write_pot_file(concat!(env!("CARGO_MANIFEST_DIR"), "/lang/default.pot")).unwrap();
 //!
 //!     // Generate the `localize_macros.rs` file
@@ -59,6 +63,19 @@
 //!
 //! Once this is done, you can start replacing your calls to `format!` with calls to `lformat!`.
 //!
+//! Localized counterparts of `print!`, `println!`, `eprint!`, `eprintln!`, `write!`, `writeln!`
+//! and `panic!` are also generated (`lprint!`, `lprintln!`, ..., `lpanic!`), so you can write
+//! `lprintln!("Hello, {}!", name)` instead of `println!("{}", lformat!("Hello, {}!", name))`.
+//! `lformat_args!` returns a value that is translated each time it is displayed, without
+//! allocating a `String`, and `lstr!("Cancel")` returns a `&'static str` for messages
+//! without arguments.
+//!
+//! Strings that can't be translated where they are defined (e.g. in a `static` table) can be
+//! marked with `lmark!("Cancel")`, which leaves them untouched but adds them to the `.pot`
+//! file, and translated later with `localize_macros::translate`. Similarly,
+//! `localize_macros::gettext` and `localize_macros::gettext_format` look up (and format)
+//! strings only known at runtime, e.g. read from a configuration file.
+//!
 //! In order to get translation, you'll need to actually translate the strings in separate
 //! files, and set your `build.rs` to load them.
 //!
@@ -105,10 +122,8 @@
 //!
 //! # Known limitations and bugs
 //!
-//! * Currently, `crowbook-intl` doesn't handle correctly raw string literals in `lformat!`
-//!   (they won't be translated correctly).
-//! * Multiple calls to the same string, but formatted differently (e.g. using a backslash
-//!   before a newline to separate a string on multiple lines) will also cause problems.
+//! * Raw string literals (`r"..."`, `r#"..."#`) can be used in `lformat!`, but not
+//!   inside a `concat!` (such messages are not extracted, and a warning is reported).
 //!
 //! # Warning
 //!
@@ -123,16 +138,26 @@
 
 extern crate regex;
 #[macro_use] extern crate lazy_static;
-extern crate walkdir;
+extern crate ignore;
+#[cfg(test)] #[macro_use] extern crate quickcheck;
 
 mod common;
+mod escape;
 mod macrogen;
 mod lang;
 mod error;
 mod localizer;
 mod message;
+mod potheader;
 mod extractor;
+mod diagnostic;
+mod pseudo;
+mod coverage;
 
-pub use error::{Result, Error};
+pub use error::{Result, Error, ErrorKind};
 pub use localizer::Localizer;
-pub use extractor::Extractor;
+pub use extractor::{Extractor, SortOrder};
+pub use diagnostic::{Diagnostic, Severity};
+pub use message::{Message, Comment, Location, AddLocation};
+pub use pseudo::PseudoLang;
+pub use coverage::{Coverage, LangCoverage, Stats, Counts, Status};
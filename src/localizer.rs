@@ -4,9 +4,13 @@
 
 use lang::Lang;
 use error::{Result, Error};
-use macrogen;
+use macrogen::{self, MacroOptions};
 use extractor::Extractor;
+use pseudo::PseudoLang;
+use coverage::{Coverage, LangCoverage, Stats, Status};
+use common::format_arguments;
 
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs::File;
 use std::path::Path;
 use std::io::Write;
@@ -31,10 +35,22 @@ use std::io::Write;
 /// localizer.add_lang("es", es).unwrap();
 /// println!("{}", localizer.generate_macro_file());
 /// ```
+///
+/// # Domains
+///
+/// If the extractor splits messages into several domains, the `.po` file of each
+/// domain can be added with `add_domain_lang`. A message is then translated with
+/// the catalog of the domain it was extracted in, and, if the generated macro is
+/// one the extractor assigns to a domain (see `Extractor::add_domain_macro`), only
+/// the catalogs of this domain are used.
 #[derive(Debug, Clone)]
 pub struct Localizer<'a> {
-    langs: Vec<Lang>,
+    // Languages, with the domain they belong to, in the order they were added
+    langs: Vec<(String, Lang)>,
     extractor: &'a Extractor,
+    options: MacroOptions,
+    pseudo_langs: Vec<PseudoLang>,
+    use_fuzzy: bool,
 }
 
 impl<'a> Localizer<'a> {
@@ -42,39 +58,508 @@ impl<'a> Localizer<'a> {
     pub fn new(extractor: &'a Extractor) -> Localizer<'a> {
         Localizer {
             langs: vec!(),
-            extractor: extractor,
+            extractor,
+            options: MacroOptions::default(),
+            pseudo_langs: vec!(),
+            use_fuzzy: false,
         }
     }
 
+    /// Sets the name of the generated format macro.
+    ///
+    /// Default is `lformat`. Using another name allows several crates (or
+    /// modules) in the same program to use different translations.
+    ///
+    /// The other generated macros are named after it: e.g. with `mylib_format`,
+    /// they are `mylib_println`, `mylib_write`, `mylib_str`, and so on. The
+    /// `Extractor` must be told about all of them with `Extractor::add_macro_family`.
+    pub fn set_macro_name<S: Into<String>>(&mut self, name: S) -> &mut Self {
+        self.options.name = name.into();
+        self
+    }
+
+    /// Sets the domain of the generated macros.
+    ///
+    /// By default, the current language is shared by the whole program and set with
+    /// `crowbook_intl_runtime::set_lang`. If a domain is set, the language state is
+    /// instead kept in the generated module, which then provides its own `set_lang`
+    /// function, so e.g. a library and the application using it can be localized
+    /// independently.
+    ///
+    /// Since the generated macro must find this state, the path of the module including
+    /// the generated file must be given if it isn't `localize_macros` at the crate root
+    /// (see `set_module_path`).
+    pub fn set_domain<S: Into<String>>(&mut self, domain: S) -> &mut Self {
+        self.options.domain = Some(domain.into());
+        self
+    }
+
+    /// Sets the path, from the crate root, of the module including the generated file
+    /// (e.g. `"i18n::macros"`).
+    ///
+    /// Only used if a domain is set. Default is `localize_macros`.
+    pub fn set_module_path<S: Into<String>>(&mut self, path: S) -> &mut Self {
+        self.options.module_path = path.into();
+        self
+    }
+
     /// Add a lang to the localizer
     ///
     /// # Arguments
     ///
     /// * `lang`: the code of the language (e.g. "fr", "en", ...);
     /// * `s`: a string containing localization information. It should be foramtted
-    /// similarly to gettext `mo` files.
+    ///   similarly to gettext `mo` files.
+    ///
+    /// Returns an error if a translation doesn't use the same format arguments
+    /// as the original string, since the generated code wouldn't compile. The
+    /// error reports the line of the offending `msgstr` (the file name, which
+    /// isn't known here, can be added with `Error::with_file`).
     pub fn add_lang<S: Into<String>>(&mut self, lang: S, s: &str) -> Result<()> {
+        self.add_domain_lang("default", lang, s)
+    }
+
+    /// Add a lang to the localizer, for the messages of a domain
+    ///
+    /// Similar to `add_lang`, which adds languages to the `"default"` domain.
+    /// The same language can be added for several domains.
+    ///
+    /// Domains that aren't assigned to a macro (see `Extractor::add_domain_dir`)
+    /// share the same generated macros, so a message extracted in several of them
+    /// can only have one translation: an error is returned if this catalog
+    /// translates it differently than a catalog added before.
+    pub fn add_domain_lang<S1, S2>(&mut self, domain: S1, lang: S2, s: &str) -> Result<()>
+        where S1: Into<String>,
+              S2: Into<String> {
+        let domain = domain.into();
         let lang = Lang::new_from_str(lang, s)?;
-        self.langs.push(lang);
+        // Check entries in the order of the file, so the first problem is reported
+        let mut keys = lang.content.keys().collect::<Vec<_>>();
+        keys.sort_by_key(|key| lang.lines.get(*key));
+        for key in keys {
+            let value = &lang.content[key];
+            let error = |msg: String| {
+                let mut error = Error::placeholder(format!("lang '{}': {}", &lang.lang, msg));
+                if let Some(&line) = lang.lines.get(key) {
+                    let snippet = s.lines().nth(line - 1).unwrap_or("");
+                    error = error.with_position(line, snippet.len() - snippet.trim_start().len() + 1)
+                        .with_snippet(snippet);
+                }
+                error
+            };
+            if lang.is_fuzzy(key) && !self.use_fuzzy {
+                // Won't be used in generated code
+                continue;
+            }
+            if self.extractor.is_literal_message(key) {
+                // Not a format string, so braces are just text
+                continue;
+            }
+            let expected = format_arguments(key)
+                .map_err(|e| error(e.message().to_owned()))?;
+            let found = format_arguments(value)
+                .map_err(|e| error(e.message().to_owned()))?;
+            if expected != found {
+                return Err(error(format!("translation \"{}\" of \"{}\" uses arguments {{{}}} \
+                                          instead of {{{}}}",
+                                         value,
+                                         key,
+                                         found.join(", "),
+                                         expected.join(", "))));
+            }
+            if let Some((other, other_value)) = self.conflicting_domain(&domain, &lang.lang, key, value) {
+                return Err(error(format!("\"{}\" is translated as \"{}\" in domain '{}' \
+                                          but as \"{}\" in domain '{}', which use the same macros",
+                                         key,
+                                         value,
+                                         domain,
+                                         other_value,
+                                         other)));
+            }
+        }
+        self.langs.push((domain, lang));
         Ok(())
     }
 
+    /// Returns a domain, sharing the generated macros with `domain`, where `key`
+    /// was also extracted but is translated differently than `value` in `lang`,
+    /// along with this other translation
+    fn conflicting_domain(&self, domain: &str, lang: &str, key: &str, value: &str) -> Option<(&str, &str)> {
+        let domains = self.extractor.message_domains(key);
+        if !domains.contains(&domain) || self.extractor.is_macro_domain(domain) {
+            return None;
+        }
+        self.langs.iter()
+            .filter(|c| c.0 != domain && c.1.lang == lang
+                    && domains.contains(&c.0.as_str())
+                    && !self.extractor.is_macro_domain(&c.0)
+                    && (self.use_fuzzy || !c.1.is_fuzzy(key)))
+            .filter_map(|c| c.1.content.get(key).map(|v| (c.0.as_str(), v.as_str())))
+            .find(|&(_, v)| v != value)
+    }
+
+    /// Add a pseudo-language, whose translations are generated from the messages
+    /// found by the extractor (see `PseudoLang`)
+    pub fn add_pseudo_lang(&mut self, pseudo: PseudoLang) -> &mut Self {
+        self.pseudo_langs.push(pseudo);
+        self
+    }
+
+    /// Returns the domains of the extractor whose messages are handled by the
+    /// generated macros
+    fn domains(&self) -> Vec<&str> {
+        match self.extractor.macro_domain(&self.options.name) {
+            Some(domain) => vec!(domain),
+            None => self.extractor.domains(),
+        }
+    }
+
+    /// Generates the pseudo-languages from the extractor's messages
+    fn generate_pseudo_langs(&self) -> Vec<Lang> {
+        let domains = self.domains();
+        self.pseudo_langs.iter()
+            .map(|pseudo| {
+                let mut lang = Lang::new(pseudo.lang());
+                for domain in &domains {
+                    for msg in self.extractor.domain_messages(domain).into_iter().flat_map(|m| m.keys()) {
+                        lang.insert(msg.as_str(), pseudo.translate(msg));
+                    }
+                }
+                lang
+            })
+            .collect()
+    }
+
+    /// Merges the catalogs of all domains, so there is one `Lang` per language
+    ///
+    /// Each message is translated using the catalog of the domain it was extracted
+    /// in if possible (`add_domain_lang` makes sure domains sharing the macros
+    /// agree), else using the first catalog (in the order they were added)
+    /// translating it.
+    fn merge_domains(&self) -> Vec<Lang> {
+        let catalogs = match self.extractor.macro_domain(&self.options.name) {
+            Some(domain) => self.langs.iter()
+                .filter(|c| c.0 == domain)
+                .collect::<Vec<_>>(),
+            None => self.langs.iter().collect(),
+        };
+        let mut langs: Vec<Lang> = vec!();
+        for catalog in &catalogs {
+            let code = &catalog.1.lang;
+            if langs.iter().any(|l| &l.lang == code) {
+                continue;
+            }
+            let same_lang = catalogs.iter()
+                .filter(|c| &c.1.lang == code)
+                .collect::<Vec<_>>();
+            let mut merged = Lang::new(code.as_str());
+            for c in &same_lang {
+                for key in c.1.content.keys() {
+                    if merged.content.contains_key(key) {
+                        continue;
+                    }
+                    let lang = self.extractor.message_domains(key)
+                        .into_iter()
+                        .filter_map(|domain| same_lang.iter()
+                                    .find(|c| c.0 == domain && c.1.content.contains_key(key)))
+                        .next()
+                        .unwrap_or(c);
+                    merged.insert(key.as_str(), lang.1.content[key].as_str());
+                    if let Some(flags) = lang.1.flags.get(key) {
+                        merged.flags.insert(key.clone(), flags.clone());
+                    }
+                }
+            }
+            langs.push(merged);
+        }
+        langs
+    }
+
+    /// Returns statistics about the translation of the extractor's messages, for
+    /// each language (pseudo-languages excepted) and each source file
+    ///
+    /// A message is translated if a non-empty translation was found, fuzzy if this
+    /// translation is marked as `fuzzy`, and untranslated otherwise. Word counts are
+    /// those of the original messages.
+    pub fn coverage(&self) -> Coverage {
+        // Messages, with the files they were found in
+        let mut messages: HashMap<&str, BTreeSet<&str>> = HashMap::new();
+        for domain in self.domains() {
+            for message in self.extractor.domain_messages(domain).into_iter().flat_map(|m| m.values()) {
+                messages.entry(message.msg.as_str())
+                    .or_default()
+                    .extend(message.locations().into_iter().map(|l| l.file.as_str()));
+            }
+        }
+        let langs = self.merge_domains()
+            .into_iter()
+            .map(|lang| {
+                let mut total = Stats::default();
+                let mut files: BTreeMap<String, Stats> = BTreeMap::new();
+                for (msg, msg_files) in &messages {
+                    let status = if !lang.content.contains_key(*msg) {
+                        Status::Untranslated
+                    } else if lang.is_fuzzy(msg) {
+                        Status::Fuzzy
+                    } else {
+                        Status::Translated
+                    };
+                    total.add(msg, status);
+                    for file in msg_files {
+                        files.entry(file.to_string()).or_default().add(msg, status);
+                    }
+                }
+                LangCoverage {
+                    lang: lang.lang,
+                    total,
+                    files,
+                }
+            })
+            .collect();
+        Coverage { langs }
+    }
+
+    /// Sets whether translations marked as `fuzzy` in `.po` files are used.
+    ///
+    /// Default is `false`: like `msgfmt`, fuzzy translations are ignored, and the
+    /// original messages are displayed instead. Since `add_lang` doesn't check
+    /// the format arguments of translations that won't be used, this must be set
+    /// before adding languages.
+    pub fn set_use_fuzzy(&mut self, use_fuzzy: bool) -> &mut Self {
+        self.use_fuzzy = use_fuzzy;
+        self
+    }
+
     /// Generate the `localization_macros.rs` file.
-    pub fn generate_macro_file(mut self) -> String {
-        macrogen::generate_macro_file(&mut self.langs, self.extractor)
+    pub fn generate_macro_file(self) -> String {
+        let mut langs = self.merge_domains();
+        if !self.use_fuzzy {
+            for lang in &mut langs {
+                lang.remove_fuzzy();
+            }
+        }
+        langs.extend(self.generate_pseudo_langs());
+        macrogen::generate_macro_file(&mut langs, self.extractor, &self.options)
     }
 
     /// Write the `localization_macros.rs` file to a file.
     pub fn write_macro_file<P:AsRef<Path>>(self, file: P) -> Result<()> {
         let mut f = File::create(file.as_ref())
-            .map_err(|e| Error::new(format!("Could not create file {file}: {error}",
-                                            file = file.as_ref().display(),
-                                            error = e)))?;
+            .map_err(|e| Error::io(format!("Could not create file {}", file.as_ref().display()), e))?;
         let content = self.generate_macro_file();
         f.write_all(content.as_bytes())
-             .map_err(|e| Error::new(format!("Could not write to file {file}: {error}",
-                                             file = file.as_ref().display(),
-                                             error = e)))?;
+             .map_err(|e| Error::io(format!("Could not write to file {}", file.as_ref().display()), e))?;
         Ok(())
     }
 }
+
+
+#[test]
+fn add_lang_arguments() {
+    let extractor = Extractor::new();
+    let mut localizer = Localizer::new(&extractor);
+    assert!(localizer.add_lang("fr", r#"
+msgid "Hello, {}"
+msgstr "Bonjour, {} {}"
+"#).is_err());
+    assert!(localizer.add_lang("fr", r#"
+msgid "Hello, {name}"
+msgstr "Bonjour, {secret}"
+"#).is_err());
+    assert!(localizer.add_lang("fr", r#"
+msgid "Hello"
+msgstr "Bonjour {"
+"#).is_err());
+    assert!(localizer.add_lang("fr", r#"
+msgid "{} of {}"
+msgstr "{1} de {0}"
+"#).is_ok());
+    let err = localizer.add_lang("fr", r#"
+msgid "Hello"
+msgstr "Bonjour"
+
+msgid "Hello, {}"
+msgstr "Bonjour"
+"#).unwrap_err();
+    assert_eq!(err.kind(), ::error::ErrorKind::Placeholder);
+    assert_eq!((err.line(), err.column()), (Some(6), Some(1)));
+    assert_eq!(err.snippet(), Some("msgstr \"Bonjour\""));
+    assert!(err.message().contains("uses arguments {} instead of {0}"));
+}
+
+#[test]
+fn generate_adversarial() {
+    let extractor = Extractor::new();
+    let mut localizer = Localizer::new(&extractor);
+    localizer.add_lang("fr\"), panic!(\"", r#"
+msgid "Hello"
+msgstr "\"), ::std::process::exit(1), (\""
+"#).unwrap();
+    let output = localizer.generate_macro_file();
+    assert!(output.contains(r#""fr\"), panic!(\"" => $m!($($pre)* "\"), ::std::process::exit(1), (\""),"#));
+}
+
+#[test]
+fn add_lang_fuzzy_arguments() {
+    let extractor = Extractor::new();
+    let po = "#, fuzzy\nmsgid \"Hello, {}\"\nmsgstr \"Bonjour\"\n";
+    let mut localizer = Localizer::new(&extractor);
+    localizer.add_lang("fr", po).unwrap();
+    assert!(!localizer.generate_macro_file().contains("Bonjour"));
+
+    let mut localizer = Localizer::new(&extractor);
+    localizer.set_use_fuzzy(true);
+    let err = localizer.add_lang("fr", po).unwrap_err();
+    assert_eq!((err.kind(), err.line()), (::error::ErrorKind::Placeholder, Some(3)));
+}
+
+#[test]
+fn add_domain_lang() {
+    let mut extractor = Extractor::new();
+    extractor.add_domain_macro("errors", "lformat_err");
+    extractor.add_messages_from_str(Path::new("main.rs"), "lformat!(\"Open\"); lformat_err!(\"Close\");")
+        .unwrap();
+
+    let core = r#"
+msgid "Open"
+msgstr "Ouvrir"
+
+msgid "Close"
+msgstr "Fermer"
+"#;
+    let errors = r#"
+msgid "Close"
+msgstr "Impossible de fermer"
+"#;
+    let mut localizer = Localizer::new(&extractor);
+    localizer.add_lang("fr", core).unwrap();
+    localizer.add_domain_lang("errors", "fr", errors).unwrap();
+    let output = localizer.clone().generate_macro_file();
+    assert!(output.contains(r#""fr" => $m!($($pre)* "Ouvrir"),"#));
+    assert!(output.contains(r#""fr" => $m!($($pre)* "Impossible de fermer"),"#));
+    assert!(!output.contains(r#""fr" => $m!($($pre)* "Fermer"),"#));
+
+    localizer.set_macro_name("lformat_err");
+    let output = localizer.generate_macro_file();
+    assert!(output.contains(r#""fr" => $m!($($pre)* "Impossible de fermer"),"#));
+    assert!(!output.contains("Ouvrir"));
+}
+
+#[test]
+fn add_domain_lang_conflict() {
+    let mut extractor = Extractor::new();
+    extractor.add_domain_dir("core", "src/core");
+    extractor.add_domain_dir("ui", "src/ui");
+    extractor.add_messages_from_str(Path::new("src/core/a.rs"), "lformat!(\"Close\"); lformat!(\"Open\");")
+        .unwrap();
+    extractor.add_messages_from_str(Path::new("src/ui/b.rs"), "lformat!(\"Close\"); lformat!(\"Open\");")
+        .unwrap();
+    assert_eq!(extractor.message_domains("Close"), vec!("core", "ui"));
+
+    let mut localizer = Localizer::new(&extractor);
+    localizer.add_domain_lang("core", "fr", "msgid \"Close\"\nmsgstr \"Fermer\"\n").unwrap();
+    localizer.add_domain_lang("core", "de", "msgid \"Close\"\nmsgstr \"Schließen\"\n").unwrap();
+    // Same translation, or another language: no conflict
+    localizer.add_domain_lang("ui", "fr", "msgid \"Close\"\nmsgstr \"Fermer\"\n").unwrap();
+    localizer.add_domain_lang("ui", "it", "msgid \"Close\"\nmsgstr \"Chiudi\"\n").unwrap();
+
+    let err = localizer.add_domain_lang("ui", "de", "msgid \"Open\"\nmsgstr \"Öffnen\"\n\
+                                                   msgid \"Close\"\nmsgstr \"Zumachen\"\n")
+        .unwrap_err();
+    assert_eq!(err.line(), Some(4));
+    assert_eq!(err.message(), "lang 'de': \"Close\" is translated as \"Zumachen\" in domain 'ui' \
+                               but as \"Schließen\" in domain 'core', which use the same macros");
+}
+
+#[test]
+fn add_pseudo_lang() {
+    let mut extractor = Extractor::new();
+    extractor.add_messages_from_str(Path::new("main.rs"), "lformat!(\"Hello, {name}!\", name = n);")
+        .unwrap();
+
+    let mut localizer = Localizer::new(&extractor);
+    localizer.add_pseudo_lang(PseudoLang::new("en-XA"));
+    let output = localizer.generate_macro_file();
+    assert!(output.contains(r#""en-XA" => $m!($($pre)* "[Ĥéļļö, {name}! ~~]", $($arg)*),"#));
+}
+
+#[test]
+fn coverage() {
+    let mut extractor = Extractor::new();
+    extractor.add_messages_from_str(Path::new("a.rs"), "lformat!(\"Open file\"); lformat!(\"Close\");")
+        .unwrap();
+    extractor.add_messages_from_str(Path::new("b.rs"), "lformat!(\"Close\"); lformat!(\"Quit now\");")
+        .unwrap();
+
+    let mut localizer = Localizer::new(&extractor);
+    localizer.add_lang("fr", r#"
+msgid "Open file"
+msgstr "Ouvrir le fichier"
+
+#, fuzzy
+msgid "Close"
+msgstr "Fermer"
+
+msgid "Quit now"
+msgstr ""
+"#).unwrap();
+    let coverage = localizer.coverage();
+    assert_eq!(coverage.langs.len(), 1);
+    let fr = &coverage.langs[0];
+    assert_eq!((fr.total.messages.translated, fr.total.messages.fuzzy, fr.total.messages.untranslated),
+               (1, 1, 1));
+    assert_eq!(fr.total.words.total(), 5);
+    assert_eq!(fr.files.keys().collect::<Vec<_>>(), vec!("a.rs", "b.rs"));
+    assert_eq!(fr.files["a.rs"].messages.translated, 1);
+    assert_eq!(fr.files["b.rs"].messages.untranslated, 1);
+    assert_eq!(fr.files["b.rs"].messages.fuzzy, 1);
+}
+
+#[test]
+fn literal_messages() {
+    let mut extractor = Extractor::new();
+    extractor.add_messages_from_str(Path::new("main.rs"), r#"lstr!("Use {braces}");
+lmark!("{weird");
+lformat!("Hello, {}", name);
+lstr!("Hello, {}");"#).unwrap();
+    let pot = extractor.generate_pot_file();
+    assert!(pot.contains("#, rust-format\nmsgid \"Hello, {}\""));
+    assert!(!pot.contains("#, rust-format\nmsgid \"Use {braces}\""));
+    assert!(!pot.contains("#, rust-format\nmsgid \"{weird\""));
+
+    let mut localizer = Localizer::new(&extractor);
+    localizer.add_lang("fr", r#"
+msgid "Use {braces}"
+msgstr "Utilisez {accolades}"
+
+msgid "{weird"
+msgstr "{bizarre"
+"#).unwrap();
+    let output = localizer.generate_macro_file();
+    assert!(output.contains(r#"($m:ident [$($pre:tt)*] "Use {braces}") => ({"#));
+    assert!(output.contains(r#""fr" => $m!($($pre)* "Utilisez {accolades}"),"#));
+}
+
+#[test]
+fn generate_skips_fuzzy() {
+    let mut extractor = Extractor::new();
+    extractor.add_messages_from_str(Path::new("a.rs"), "lformat!(\"Open\"); lformat!(\"Close\");")
+        .unwrap();
+    let mut localizer = Localizer::new(&extractor);
+    localizer.add_lang("fr", r#"
+msgid "Open"
+msgstr "Ouvrir"
+
+#, fuzzy
+msgid "Close"
+msgstr "Fermer"
+"#).unwrap();
+    let output = localizer.clone().generate_macro_file();
+    assert!(output.contains("\"Ouvrir\""));
+    assert!(!output.contains("\"Fermer\""));
+
+    localizer.set_use_fuzzy(true);
+    let output = localizer.generate_macro_file();
+    assert!(output.contains(r#""fr" => $m!($($pre)* "Fermer"),"#));
+    assert!(output.contains(r#""Close" => Some("Fermer"),"#));
+}
@@ -4,125 +4,469 @@
 
 use lang::Lang;
 use extractor::Extractor;
+use common::has_arguments;
+use escape::escape_rust;
 
-/// Generate the `lformat!` macro
-pub fn generate_lformat(langs: &mut [Lang], extractor: &Extractor) -> String {
+/// Options for generating the macros
+#[derive(Debug, Clone)]
+pub struct MacroOptions {
+    /// Name of the generated format macro
+    pub name: String,
+    /// Domain, if language state is kept in the generated module instead of
+    /// the runtime
+    pub domain: Option<String>,
+    /// Path (from the crate root) of the module the generated file is included in
+    pub module_path: String,
+}
+
+impl Default for MacroOptions {
+    fn default() -> MacroOptions {
+        MacroOptions {
+            name: String::from("lformat"),
+            domain: None,
+            module_path: String::from("localize_macros"),
+        }
+    }
+}
+
+impl MacroOptions {
+    /// Returns the path of the function returning the current language
+    fn get_lang(&self) -> String {
+        if self.domain.is_some() {
+            format!("$crate::{}::__get_lang", self.module_path)
+        } else {
+            String::from("::crowbook_intl_runtime::__get_lang")
+        }
+    }
+}
+
+/// Names of the generated macros other than the format macro, with the macro
+/// they dispatch to and whether they take a destination first
+pub const COMPANIONS: &[(&str, &str, bool)] = &[("print", "print", false),
+                                            ("println", "println", false),
+                                            ("eprint", "eprint", false),
+                                            ("eprintln", "eprintln", false),
+                                            ("write", "write", true),
+                                            ("writeln", "writeln", true),
+                                            ("panic", "panic", false)];
+
+/// Returns the name of a companion macro of the format macro `name`, e.g.
+/// `lprintln` for `lformat` or `mylib_println` for `mylib_format`
+pub fn companion_name(name: &str, suffix: &str) -> String {
+    match name.strip_suffix("format") {
+        Some(prefix) => format!("{}{}", prefix, suffix),
+        None => format!("{}_{}", name, suffix),
+    }
+}
+
+impl MacroOptions {
+    /// Returns the name of a companion macro of the format macro (see `companion_name`)
+    pub fn companion_name(&self, suffix: &str) -> String {
+        companion_name(&self.name, suffix)
+    }
+
+    /// Returns the name of the internal macro doing the translation
+    fn impl_name(&self) -> String {
+        format!("__{}_impl", self.name)
+    }
+}
+
+/// Returns true if the translation of `msg` is passed arguments, i.e. if it is a
+/// format string (as opposed to messages only used with `lstr!` or `lmark!`)
+/// containing arguments
+fn takes_arguments(extractor: &Extractor, msg: &str) -> bool {
+    !extractor.is_literal_message(msg) && has_arguments(msg)
+}
+
+/// Generate the `lformat!` macro (or its equivalent under another name), along
+/// with `lstr!`, `lprintln!`, `lformat_args!` and the other localized counterparts
+/// of the standard formatting macros, and `lmark!`
+///
+/// All of them call an internal macro, which matches the message, translates it,
+/// and passes it to the macro given as first token (`format`, `println`, ...).
+pub fn generate_lformat(langs: &mut [Lang], extractor: &Extractor, options: &MacroOptions) -> String {
+    let name = options.impl_name();
     let mut arg_variant = String::new();
     let mut noarg_variant = String::new();
 
     for i in 0..langs.len() {
         let (curr, rest) = langs.split_at_mut(i + 1);
-        let ref mut hash = curr[i].content;
+        let hash = &mut curr[i].content;
 
         // Write keys and translations from the po files
         for (key, value) in hash {
-            let b = has_arguments(key);
+            let b = takes_arguments(extractor, key);
+            let args = if b { ", $($arg)*" } else { "" };
             let mut inner = String::new();
-            if b {
-                inner.push_str(&format!("            \"{}\" => format!(\"{}\", $($arg)*),\n",
-                                       curr[i].lang,
-                                       value));
-            } else {
-                inner.push_str(&format!("            \"{}\" => format!(\"{}\"),\n",
-                                       curr[i].lang,
-                                       value));
-            }
+            inner.push_str(&format!("            \"{}\" => $m!($($pre)* \"{}\"{}),\n",
+                                    escape_rust(&curr[i].lang),
+                                    escape_rust(value),
+                                    args));
 
             for other_lang in rest.iter_mut() {
-                let ref mut hash = other_lang.content;
+                let hash = &mut other_lang.content;
                 if let Some(value) = hash.remove(key) {
-                    if b {
-                        inner.push_str(&format!("            \"{}\" => format!(\"{}\", $($arg)*),\n",
-                                                other_lang.lang,
-                                                value));
-                    } else {
-                        inner.push_str(&format!("            \"{}\" => format!(\"{}\"),\n",
-                                                        other_lang.lang,
-                                                        value));
-                    }
+                    inner.push_str(&format!("            \"{}\" => $m!($($pre)* \"{}\"{}),\n",
+                                            escape_rust(&other_lang.lang),
+                                            escape_rust(&value),
+                                            args));
                 }
             }
 
+            inner.push_str(&format!("            _ => $m!($($pre)* \"{}\"{}),\n",
+                                    escape_rust(key),
+                                    args));
             
-            if b {
-                inner.push_str(&format!("            _ => format!(\"{}\", $($arg)*),\n",
-                                        key));
-            } else {
-                inner.push_str(&format!("            _ => format!(\"{}\"),\n",
-                                        key));
-            }
-            
-            let this_variant = format!("        let __guard = ::crowbook_intl_runtime::__get_lang();
+            let this_variant = format!("        let __guard = {}();
         match __guard.as_str() {{
 {}        }}",
+            options.get_lang(),
             inner);
 
             if b {
-                arg_variant.push_str(&format!("    (\"{}\", $($arg:tt)*) => ({{
+                arg_variant.push_str(&format!("    ($m:ident [$($pre:tt)*] \"{}\", $($arg:tt)*) => ({{
 {}
     }});\n",
-                                              key, this_variant));
+                                              escape_rust(key), this_variant));
             } else {
-                noarg_variant.push_str(&format!("    (\"{}\") => ({{
+                noarg_variant.push_str(&format!("    ($m:ident [$($pre:tt)*] \"{}\") => ({{
 {}
     }});\n",
-                                                key, this_variant));
+                                                escape_rust(key), this_variant));
             }
         }
+    }
+
+    // Add translations from exact msg formats used in the macro to the ones
+    // Used in .po files (e.g. might not have the same escape codes)
+    for (key, value) in extractor.original_strings() {
+        if takes_arguments(extractor, value) {
+            arg_variant.push_str(&format!("    ($m:ident [$($pre:tt)*] \"{}\", $($arg:tt)*) => ({}!($m [$($pre)*] \"{}\", $($arg)*));\n",
+                                          key, name, escape_rust(value)));
+        } else {
+            noarg_variant.push_str(&format!("    ($m:ident [$($pre:tt)*] \"{}\") => ({}!($m [$($pre)*] \"{}\"));\n",
+                                          key, name, escape_rust(value)));
+        }
+    }
 
-        // Add translations from exact msg formats used in lformat! to the ones
-        // Used in .po files (e.g. might not have the same escape codes)
-        for (key, value) in extractor.original_strings() {
-            if has_arguments(key) {
-                arg_variant.push_str(&format!("    (\"{}\", $($arg:tt)*) => (lformat!(\"{}\", $($arg)*));\n",
-                                              key, value));
-            } else {
-                noarg_variant.push_str(&format!("    (\"{}\") => (lformat!(\"{}\"));\n",
-                                              key, value));
-            }
+    // Same for raw string literals, whose token is matched as written
+    for (token, value) in extractor.raw_strings() {
+        if takes_arguments(extractor, value) {
+            arg_variant.push_str(&format!("    ($m:ident [$($pre:tt)*] {}, $($arg:tt)*) => ({}!($m [$($pre)*] \"{}\", $($arg)*));\n",
+                                          token, name, escape_rust(value)));
+        } else {
+            noarg_variant.push_str(&format!("    ($m:ident [$($pre:tt)*] {}) => ({}!($m [$($pre)*] \"{}\"));\n",
+                                            token, name, escape_rust(value)));
         }
     }
 
-    format!("/// Localized format macro (or `lformat!` in short)
+    // Same for `concat!` of string literals, which are matched to the concatenated string
+    for (parts, value) in extractor.concat_strings() {
+        let key = parts.iter()
+            .map(|s| format!("\"{}\"", s))
+            .collect::<Vec<_>>()
+            .join(", ");
+        if takes_arguments(extractor, value) {
+            arg_variant.push_str(&format!("    ($m:ident [$($pre:tt)*] concat!({} $(,)*), $($arg:tt)*) => ({}!($m [$($pre)*] \"{}\", $($arg)*));\n",
+                                          key, name, escape_rust(value)));
+        } else {
+            noarg_variant.push_str(&format!("    ($m:ident [$($pre:tt)*] concat!({} $(,)*)) => ({}!($m [$($pre)*] \"{}\"));\n",
+                                            key, name, escape_rust(value)));
+        }
+    }
+
+    let mut output = format!("#[doc(hidden)]
+macro_rules! {} {{
+{}{}    ($m:ident [$($pre:tt)*] $($arg:tt)*) => ($m!($($pre)* $($arg)*));
+}}
+
+/// Localized format macro (or `{}!` in short)
 ///
 /// Should be similar to `format!`, except strings are localized.
 /// Generated automatically, you should not edit it.
-macro_rules! lformat {{
-{}{}    ($($arg:tt)*) => (format!($($arg)*));
-}}",
-            &arg_variant,
-            &noarg_variant)
+macro_rules! {} {{
+    ($($arg:tt)*) => ({}!(format [] $($arg)*));
+}}
+",
+                             name,
+                             &arg_variant,
+                             &noarg_variant,
+                             options.name,
+                             options.name,
+                             name);
+
+    for &(suffix, target, writer) in COMPANIONS {
+        output.push_str(&format!("
+/// Localized version of `{target}!`
+#[allow(unused_macros)]
+macro_rules! {companion} {{
+{arms}}}
+",
+                                 target = target,
+                                 companion = options.companion_name(suffix),
+                                 arms = if writer {
+                                     format!("    ($dst:expr) => ({target}!($dst));
+    ($dst:expr, $($arg:tt)*) => ({name}!({target} [$dst,] $($arg)*));
+", target = target, name = name)
+                                 } else {
+                                     format!("    ($($arg:tt)*) => ({name}!({target} [] $($arg)*));
+", target = target, name = name)
+                                 }));
+    }
+
+    output.push_str(&format!("
+/// Localized version of `format_args!`
+///
+/// Returns a value implementing `Display`, which is translated (according to the
+/// language at this time) each time it is formatted, without allocating. Arguments
+/// are borrowed, and evaluated each time the value is formatted.
+#[allow(unused_macros)]
+macro_rules! {companion} {{
+    ($($arg:tt)*) => ({{
+        struct __LocalizedArgs<F>(F);
+        impl<F> ::std::fmt::Display for __LocalizedArgs<F>
+            where F: Fn(&mut ::std::fmt::Formatter) -> ::std::fmt::Result {{
+            fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {{
+                (self.0)(f)
+            }}
+        }}
+        __LocalizedArgs(|__f: &mut ::std::fmt::Formatter| {name}!(write [__f,] $($arg)*))
+    }});
+}}
+",
+                             companion = options.companion_name("format_args"),
+                             name = name));
+
+    output.push_str(&format!("
+/// Marks a message for translation, without translating it
+///
+/// Expands to the string itself, so it can be used in `static` tables; the message
+/// is extracted to the `.pot` file, and can be translated later with `translate`.
+#[allow(unused_macros)]
+macro_rules! {companion} {{
+    ($msg:expr) => ($msg);
+}}
+",
+                             companion = options.companion_name("mark")));
+
+    output.push_str(&format!("
+/// Localized string, for messages without arguments
+///
+/// Returns a `&'static str`, without allocating. Unlike `{format}!`, the message
+/// is returned as is (e.g. `{{{{` isn't replaced by `{{`).
+#[allow(unused_macros)]
+macro_rules! {companion} {{
+    ($($arg:tt)*) => ({name}!(concat [] $($arg)*));
+}}
+",
+                             format = options.name,
+                             companion = options.companion_name("str"),
+                             name = name));
+    output
+}
+
+/// Generate the functions keeping the language state of a domain
+///
+/// The state is a `static RwLock`, whose `const` constructor requires Rust 1.63
+/// (below the crate's `rust-version`).
+pub fn generate_domain_state(domain: &str, name: &str) -> String {
+    format!("/// Current language of the `{domain}` domain
+#[allow(dead_code)]
+static __LANG: ::std::sync::RwLock<String> = ::std::sync::RwLock::new(String::new());
+
+/// Sets the language used by the `{name}!` macro (domain `{domain}`)
+///
+/// This is independent of `crowbook_intl_runtime::set_lang`, and of other domains.
+#[allow(dead_code)]
+pub fn set_lang(lang: &str) {{
+    *__LANG.write().unwrap() = lang.to_owned();
+}}
+
+#[doc(hidden)]
+#[allow(dead_code)]
+pub fn __get_lang() -> ::std::sync::RwLockReadGuard<'static, String> {{
+    __LANG.read().unwrap()
+}}
+
+",
+            domain = domain.replace('`', "'"),
+            name = name)
 }
 
 
+/// Generate the `gettext`, `gettext_format` and `translate` functions, which look
+/// up messages that are only known at runtime (e.g. marked with `lmark!`, or read
+/// from a configuration file)
+pub fn generate_lookup(langs: &[Lang], options: &MacroOptions) -> String {
+    let mut arms = String::new();
+    for lang in langs {
+        let mut keys = lang.content.keys().collect::<Vec<_>>();
+        keys.sort();
+        arms.push_str(&format!("        \"{}\" => match msgid {{\n", escape_rust(&lang.lang)));
+        for key in keys {
+            arms.push_str(&format!("            \"{}\" => Some(\"{}\"),\n",
+                                   escape_rust(key),
+                                   escape_rust(&lang.content[key])));
+        }
+        arms.push_str("            _ => None,\n        },\n");
+    }
+    let get_lang = if options.domain.is_some() {
+        "__get_lang"
+    } else {
+        "::crowbook_intl_runtime::__get_lang"
+    };
+    format!("/// Returns the translation of `msgid` in the current language, if there is one
+///
+/// Unlike `{name}!`, this works with messages that are not literals. `msgid` is the
+/// message as it would be written in `{name}!` (so it is a format string if the message
+/// has arguments), and the translation is returned as is, without formatting: see
+/// `gettext_format` for messages with arguments.
+#[allow(dead_code)]
+pub fn gettext(msgid: &str) -> Option<&'static str> {{
+    let __guard = {get_lang}();
+    match __guard.as_str() {{
+{arms}        _ => None,
+    }}
+}}
+
+/// Returns the translation of `msgid` in the current language (or `msgid` itself if
+/// there is none), formatted with `args`
+///
+/// Only `{{}}` and positional (e.g. `{{1}}`) arguments are replaced, as well as `{{{{`
+/// and `}}}}`; other placeholders (named arguments, format specifications, or arguments
+/// missing from `args`) are kept as is, since formatting happens at runtime.
+#[allow(dead_code)]
+pub fn gettext_format<T: ::std::fmt::Display>(msgid: &str, args: &[T]) -> String {{
+    use std::fmt::Write;
+    let fmt = gettext(msgid).unwrap_or(msgid);
+    let mut res = String::with_capacity(fmt.len());
+    let mut next = 0;
+    let mut rest = fmt;
+    while let Some(i) = rest.find(|c| c == '{{' || c == '}}') {{
+        res.push_str(&rest[..i]);
+        let tail = &rest[i..];
+        if tail.starts_with(\"{{{{\") || tail.starts_with(\"}}}}\") {{
+            res.push_str(&tail[..1]);
+            rest = &tail[2..];
+            continue;
+        }}
+        let end = match tail.find('}}') {{
+            Some(end) if tail.starts_with('{{') => end,
+            _ => {{
+                res.push_str(&tail[..1]);
+                rest = &tail[1..];
+                continue;
+            }},
+        }};
+        let spec = &tail[1..end];
+        let arg = if spec.is_empty() {{
+            next += 1;
+            args.get(next - 1)
+        }} else {{
+            spec.parse::<usize>().ok().and_then(|n| args.get(n))
+        }};
+        match arg {{
+            Some(arg) => {{
+                let _ = write!(res, \"{{}}\", arg);
+            }},
+            None => res.push_str(&tail[..end + 1]),
+        }}
+        rest = &tail[end + 1..];
+    }}
+    res.push_str(rest);
+    res
+}}
+
+/// Returns the translation of `msg` in the current language, or `msg` itself if
+/// there is none
+///
+/// Useful for messages marked for translation with `{mark}!`, e.g. in a static
+/// table. Like `gettext`, the translation is returned as is, without formatting.
+#[allow(dead_code)]
+pub fn translate<'a>(msg: &'a str) -> ::std::borrow::Cow<'a, str> {{
+    ::std::borrow::Cow::Borrowed(gettext(msg).unwrap_or(msg))
+}}
+
+",
+            name = options.name,
+            mark = options.companion_name("mark"),
+            get_lang = get_lang,
+            arms = arms)
+}
+
 /// Generate the file containing the localization macros
-pub fn generate_macro_file(langs: &mut [Lang], extractor: &Extractor) -> String {
+pub fn generate_macro_file(langs: &mut [Lang], extractor: &Extractor, options: &MacroOptions) -> String {
     let mut output = String::from(include_str!("../data/localize_macros.rs"));
-    output.push_str(&generate_lformat(langs, extractor));
+    if let Some(ref domain) = options.domain {
+        output.push_str(&generate_domain_state(domain, &options.name));
+    }
+    output.push_str(&generate_lookup(langs, options));
+    output.push_str(&generate_lformat(langs, extractor, options));
     output
 }
 
+#[test]
+fn generate_lformat_escapes() {
+    let mut lang = Lang::new("fr");
+    lang.insert("Path: {}", "Chemin\t: {} C:\\");
+    lang.insert("Quote", "\"); panic!(\"");
+    let output = generate_lformat(&mut [lang], &Extractor::new(), &MacroOptions::default());
+    assert!(output.contains(r#"($m:ident [$($pre:tt)*] "Path: {}", $($arg:tt)*) => ({"#));
+    assert!(output.contains(r#""fr" => $m!($($pre)* "Chemin\t: {} C:\\", $($arg)*),"#));
+    assert!(output.contains(r#""fr" => $m!($($pre)* "\"); panic!(\""),"#));
+}
 
-/// Returns true if s contains arguments, false else
-fn has_arguments(s: &str) -> bool {
-    let chars:Vec<_> = s.chars().collect();
-    for i in 0..chars.len() {
-        let c = chars[i];
-        if c == '{' || c == '}' {
-            if i >= chars.len() - 1 {
-                return true;
-            } else {
-                let next_c = chars[i+1];
-                return !(c == next_c);
-            }
-        }
-    }
-    false
+#[test]
+fn generate_lformat_raw_strings() {
+    let mut extractor = Extractor::new();
+    extractor.add_messages_from_str(::std::path::Path::new("a.rs"),
+                                    r###"lformat!(r#"Say "{}""#, x); lformat!(r"C:\");"###).unwrap();
+    let output = generate_lformat(&mut [], &extractor, &MacroOptions::default());
+    assert!(output.contains(r###"($m:ident [$($pre:tt)*] r#"Say "{}""#, $($arg:tt)*) => (__lformat_impl!($m [$($pre)*] "Say \"{}\"", $($arg)*));"###));
+    assert!(output.contains(r###"($m:ident [$($pre:tt)*] r"C:\") => (__lformat_impl!($m [$($pre)*] "C:\\"));"###));
+}
+
+#[test]
+fn generate_named_macro() {
+    let mut lang = Lang::new("fr");
+    lang.insert("Hello", "Bonjour");
+    let options = MacroOptions {
+        name: String::from("mylib_format"),
+        domain: Some(String::from("mylib")),
+        module_path: String::from("i18n::macros"),
+    };
+    let output = generate_macro_file(&mut [lang], &Extractor::new(), &options);
+    assert!(output.contains("macro_rules! mylib_format {"));
+    assert!(output.contains("let __guard = $crate::i18n::macros::__get_lang();"));
+    assert!(output.contains("pub fn set_lang(lang: &str)"));
+    assert!(!output.contains("macro_rules! lformat"));
+}
+
+#[test]
+fn generate_companion_macros() {
+    let output = generate_lformat(&mut [], &Extractor::new(), &MacroOptions::default());
+    assert!(output.contains("macro_rules! lprintln {\n    ($($arg:tt)*) => (__lformat_impl!(println [] $($arg)*));"));
+    assert!(output.contains("    ($dst:expr, $($arg:tt)*) => (__lformat_impl!(writeln [$dst,] $($arg)*));"));
+    assert!(output.contains("macro_rules! lpanic {"));
+    assert!(output.contains("macro_rules! lformat_args {"));
+    assert!(output.contains("    ($($arg:tt)*) => (__lformat_impl!(concat [] $($arg)*));"));
+    let options = MacroOptions {
+        name: String::from("mylib_format"),
+        .. MacroOptions::default()
+    };
+    assert_eq!(&options.companion_name("eprintln"), "mylib_eprintln");
 }
 
 #[test]
-fn test_arguments() {
-    assert_eq!(has_arguments("foo bar"), false);
-    assert_eq!(has_arguments("foo {}"), true);
-    assert_eq!(has_arguments("foo {{bar}}"), false);
+fn generate_lookup_fns() {
+    let mut lang = Lang::new("fr");
+    lang.insert("Cancel", "Annuler");
+    lang.insert("Say \"{}\"", "Dire « {} »");
+    let output = generate_lookup(&[lang], &MacroOptions::default());
+    assert!(output.contains("pub fn gettext(msgid: &str) -> Option<&'static str> {"));
+    assert!(output.contains("    let __guard = ::crowbook_intl_runtime::__get_lang();"));
+    assert!(output.contains("        \"fr\" => match msgid {\n            \"Cancel\" => Some(\"Annuler\"),\n"));
+    assert!(output.contains(r#"            "Say \"{}\"" => Some("Dire « {} »"),"#));
+    assert!(output.contains("pub fn gettext_format<T: ::std::fmt::Display>(msgid: &str, args: &[T]) -> String {"));
+    assert!(output.contains("pub fn translate<'a>(msg: &'a str) -> ::std::borrow::Cow<'a, str> {"));
 }
@@ -3,22 +3,69 @@
 // this file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use std::fmt;
-use common::escape_string;
+use escape::escape_po;
+
+/// Location of an occurrence of a message in source code
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct Location {
+    /// Path of the source file
+    pub file: String,
+    /// Line of the string literal (starting at 1)
+    pub line: usize,
+    /// Column of the string literal, in characters (starting at 1)
+    pub column: usize,
+    /// Byte offset of the start of the string literal (opening quote included)
+    pub start: usize,
+    /// Byte offset of the end of the string literal (closing quote included)
+    pub end: usize,
+}
+
+impl Location {
+    /// Creates a new location with only a file and line (column and span are set to 0)
+    pub fn new<S:Into<String>>(file: S, line: usize) -> Location {
+        Location {
+            file: file.into(),
+            line,
+            column: 0,
+            start: 0,
+            end: 0,
+        }
+    }
+}
 
 /// Represents a comment concerning the location/translation of a message
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub enum Comment {
-    /// File and line
-    Source(String, usize)
+    /// Location in source code
+    Source(Location)
 }
 
 
+/// Which source references are written on the `#:` line of a `.pot` entry
+/// (similar to `xgettext`'s `--add-location` option)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddLocation {
+    /// File and line (default)
+    Full,
+    /// File only
+    File,
+    /// No source reference
+    Never,
+}
+
 /// Represents a message, with a string and a list of comments
 /// corresponding to position in source file
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub struct Message {
+    /// Comments, sorted (by file, then line) so messages are ordered by their
+    /// first location whatever the order files were read in
     pub comments: Vec<Comment>,
     pub msg: String,
+    /// Flags (e.g. `rust-format`) written on the `#,` line
+    pub flags: Vec<String>,
+    /// Whether the message is used as a format string (e.g. by `lformat!`, as
+    /// opposed to `lstr!` or `lmark!`)
+    pub format: bool,
 }
 
 
@@ -28,28 +75,106 @@ impl Message {
         Message {
             msg: msg.into(),
             comments: vec!(),
+            flags: vec!(),
+            format: false,
         }
     }
 
     /// Add a source location to a comment
     pub fn add_source<S:Into<String>>(&mut self, file: S, line: usize) -> &mut Self {
-        self.comments.push(Comment::Source(file.into(), line));
+        self.add_location(Location::new(file, line))
+    }
+
+    /// Add a source location, with column and span, to a comment
+    ///
+    /// Locations are kept sorted, by file and then position.
+    pub fn add_location(&mut self, location: Location) -> &mut Self {
+        let comment = Comment::Source(location);
+        let pos = match self.comments.binary_search(&comment) {
+            Ok(pos) | Err(pos) => pos,
+        };
+        self.comments.insert(pos, comment);
         self
     }
-}
 
+    /// Returns the locations of all occurrences of this message in source code
+    pub fn locations(&self) -> Vec<&Location> {
+        self.comments
+            .iter()
+            .map(|c| match *c {
+                Comment::Source(ref location) => location,
+            })
+            .collect()
+    }
 
-impl fmt::Display for Message {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "#:")?;
-        for comment in &self.comments {
-            match *comment {
-                Comment::Source(ref file, line) => write!(f, " {}:{}", file, line)?,
+    /// Add a flag to the message, if it isn't already present
+    pub fn add_flag<S:Into<String>>(&mut self, flag: S) -> &mut Self {
+        let flag = flag.into();
+        if !self.flags.contains(&flag) {
+            self.flags.push(flag);
+        }
+        self
+    }
+
+    /// Write the message as a `.pot` entry, with source references as
+    /// specified by `location`
+    pub fn write_entry<W: fmt::Write>(&self, f: &mut W, location: AddLocation) -> fmt::Result {
+        if location != AddLocation::Never && !self.comments.is_empty() {
+            write!(f, "#:")?;
+            let mut files = vec!();
+            let mut comments = self.comments.iter().collect::<Vec<_>>();
+            comments.sort();
+            for comment in comments {
+                match *comment {
+                    Comment::Source(ref source) => if location == AddLocation::Full {
+                        write!(f, " {}:{}", source.file, source.line)?;
+                    } else if !files.contains(&&source.file) {
+                        files.push(&source.file);
+                        write!(f, " {}", source.file)?;
+                    },
+                }
             }
+            writeln!(f)?;
+        }
+        if !self.flags.is_empty() {
+            writeln!(f, "#, {}", self.flags.join(", "))?;
         }
-        writeln!(f, "
-msgid \"{}\"
+        writeln!(f, "msgid \"{}\"
 msgstr \"\"\n",
-                 escape_string(self.msg.as_str()))
+                 escape_po(&self.msg))
     }
 }
+
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write_entry(f, AddLocation::Full)
+    }
+}
+
+#[test]
+fn message_flags() {
+    let mut message = Message::new("Hello, {}");
+    message.add_source("src/main.rs", 12);
+    message.add_flag("rust-format");
+    message.add_flag("rust-format");
+    assert_eq!(&format!("{}", message), "#: src/main.rs:12
+#, rust-format
+msgid \"Hello, {}\"
+msgstr \"\"
+
+");
+}
+
+#[test]
+fn message_add_location() {
+    let mut message = Message::new("foo");
+    message.add_source("src/main.rs", 12);
+    message.add_source("src/main.rs", 42);
+    let mut s = String::new();
+    message.write_entry(&mut s, AddLocation::File).unwrap();
+    assert!(s.starts_with("#: src/main.rs\nmsgid"));
+    let mut s = String::new();
+    message.write_entry(&mut s, AddLocation::Never).unwrap();
+    assert!(s.starts_with("msgid"));
+}
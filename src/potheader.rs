@@ -0,0 +1,190 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use escape::escape_po;
+
+use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Information written in the header of a `.pot` file
+///
+/// Missing fields are replaced by the same placeholders `xgettext` uses.
+#[derive(Debug, Clone)]
+pub struct PotHeader {
+    pub project_name: Option<String>,
+    pub project_version: Option<String>,
+    pub bug_report_address: Option<String>,
+    pub copyright_holder: Option<String>,
+    /// Creation date, in seconds since UNIX epoch
+    pub creation_date: Option<u64>,
+    /// If set, never use the current time
+    pub reproducible: bool,
+}
+
+impl PotHeader {
+    /// Creates a new header, with default values read from the environment
+    /// variables set by Cargo when running a build script
+    pub fn from_env() -> PotHeader {
+        PotHeader {
+            project_name: env::var("CARGO_PKG_NAME").ok(),
+            project_version: env::var("CARGO_PKG_VERSION").ok(),
+            bug_report_address: env::var("CARGO_PKG_REPOSITORY").ok()
+                .and_then(|s| if s.is_empty() { None } else { Some(s) }),
+            copyright_holder: env::var("CARGO_PKG_AUTHORS").ok()
+                .and_then(|s| if s.is_empty() { None } else { Some(s.replace(':', ", ")) }),
+            creation_date: None,
+            reproducible: false,
+        }
+    }
+
+    /// Returns the creation date that must be written, if any
+    ///
+    /// * if a date has been set explicitly, returns it;
+    /// * if `SOURCE_DATE_EPOCH` is set, returns it;
+    /// * in reproducible mode, returns `None`;
+    /// * else, returns the current time.
+    pub fn date(&self) -> Option<u64> {
+        self.date_with_source(env::var("SOURCE_DATE_EPOCH").ok()
+                              .and_then(|s| s.trim().parse().ok()))
+    }
+
+    /// Same as `date`, `source_date` being the value of `SOURCE_DATE_EPOCH`
+    fn date_with_source(&self, source_date: Option<u64>) -> Option<u64> {
+        if self.creation_date.is_some() {
+            self.creation_date
+        } else if source_date.is_some() || self.reproducible {
+            source_date
+        } else {
+            SystemTime::now().duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|d| d.as_secs())
+        }
+    }
+
+    /// Renders the header
+    ///
+    /// Newlines in fields are replaced by spaces, since they would end comment
+    /// lines or split header fields, and fields are escaped in the `msgstr`.
+    pub fn render(&self) -> String {
+        let date = self.date();
+        let project = one_line(self.project_name.as_deref().unwrap_or("PACKAGE"));
+        let version = one_line(self.project_version.as_deref().unwrap_or("VERSION"));
+        let bugs = one_line(self.bug_report_address.as_deref().unwrap_or(""));
+        let holder = one_line(self.copyright_holder.as_deref()
+                              .unwrap_or("THE PACKAGE'S COPYRIGHT HOLDER"));
+        let year = date.map(|d| format!("{}", civil_from_timestamp(d).0))
+            .unwrap_or_else(|| String::from("YEAR"));
+        let mut output = format!("# Translations template for {project}.
+# Copyright (C) {year} {holder}
+# This file is distributed under the same license as the {project} package.
+# FIRST AUTHOR <EMAIL@ADDRESS>, {year}.
+#
+#, fuzzy
+msgid \"\"
+msgstr \"\"
+\"Project-Id-Version: {escaped_project} {version}\\n\"
+\"Report-Msgid-Bugs-To: {bugs}\\n\"
+",
+                                 project = project,
+                                 escaped_project = escape_po(&project),
+                                 year = year,
+                                 holder = holder,
+                                 version = escape_po(&version),
+                                 bugs = escape_po(&bugs));
+        if let Some(date) = date {
+            output.push_str(&format!("\"POT-Creation-Date: {}\\n\"\n", format_date(date)));
+        }
+        output.push_str(r#""PO-Revision-Date: YEAR-MO-DA HO:MI+ZONE\n"
+"Last-Translator: FULL NAME <EMAIL@ADDRESS>\n"
+"Language-Team: LANGUAGE <LL@li.org>\n"
+"MIME-Version: 1.0\n"
+"Content-Type: text/plain; charset=UTF-8\n"
+"Content-Transfer-Encoding: 8bit\n"
+
+"#);
+        output
+    }
+}
+
+/// Replaces line breaks by spaces
+fn one_line(s: &str) -> String {
+    s.replace("\r\n", " ").replace(['\r', '\n'], " ")
+}
+
+/// Formats a timestamp the way gettext does, e.g. `2017-03-04 12:00+0000`
+fn format_date(timestamp: u64) -> String {
+    let (year, month, day) = civil_from_timestamp(timestamp);
+    let secs = timestamp % 86400;
+    format!("{:04}-{:02}-{:02} {:02}:{:02}+0000",
+            year, month, day, secs / 3600, (secs % 3600) / 60)
+}
+
+/// Converts a timestamp to a (year, month, day) date in UTC
+fn civil_from_timestamp(timestamp: u64) -> (u64, u64, u64) {
+    // See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = timestamp / 86400 + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+
+#[test]
+fn format_date_1() {
+    assert_eq!(&format_date(0), "1970-01-01 00:00+0000");
+    assert_eq!(&format_date(1488628800), "2017-03-04 12:00+0000");
+    assert_eq!(&format_date(951782400), "2000-02-29 00:00+0000");
+}
+
+#[test]
+fn render_header_1() {
+    let header = PotHeader {
+        project_name: Some(String::from("foo")),
+        project_version: Some(String::from("1.0")),
+        bug_report_address: None,
+        copyright_holder: Some(String::from("Jane Doe")),
+        creation_date: Some(1488628800),
+        reproducible: false,
+    };
+    let s = header.render();
+    assert!(s.contains("# Copyright (C) 2017 Jane Doe\n"));
+    assert!(s.contains("\"Project-Id-Version: foo 1.0\\n\"\n"));
+    assert!(s.contains("\"Report-Msgid-Bugs-To: \\n\"\n"));
+    assert!(s.contains("\"POT-Creation-Date: 2017-03-04 12:00+0000\\n\"\n"));
+}
+
+#[test]
+fn render_header_escapes() {
+    let header = PotHeader {
+        project_name: Some(String::from("my \"proj\"")),
+        project_version: Some(String::from("1.0\\")),
+        bug_report_address: Some(String::from("a@b.org\nX-Injected: yes")),
+        copyright_holder: Some(String::from("Jane\r\nDoe")),
+        creation_date: Some(1488628800),
+        reproducible: false,
+    };
+    let s = header.render();
+    assert!(s.starts_with("# Translations template for my \"proj\".\n# Copyright (C) 2017 Jane Doe\n"));
+    assert!(s.contains("\"Project-Id-Version: my \\\"proj\\\" 1.0\\\\\\n\"\n"));
+    assert!(s.contains("\"Report-Msgid-Bugs-To: a@b.org X-Injected: yes\\n\"\n"));
+    ::lang::Lang::new_from_str("fr", &s).unwrap();
+}
+
+#[test]
+fn header_date() {
+    let mut header = PotHeader::from_env();
+    assert_eq!(header.date_with_source(Some(42)), Some(42));
+    assert!(header.date_with_source(None).is_some());
+    header.reproducible = true;
+    assert_eq!(header.date_with_source(Some(42)), Some(42));
+    assert_eq!(header.date_with_source(None), None);
+    header.creation_date = Some(1);
+    assert_eq!(header.date_with_source(Some(42)), Some(1));
+}
@@ -0,0 +1,168 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with
+// this file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+const ASCII_LOWER: &str = "abcdefghijklmnopqrstuvwxyz";
+const ACCENTED_LOWER: &str = "åƀçđéƒĝĥîĵķļɱñöþǫŕšţûṽŵẋýž";
+const ASCII_UPPER: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const ACCENTED_UPPER: &str = "ÅƁÇĐÉƑĜĤÎĴĶĻṀÑÖÞǪŔŠŢÛṼŴẊÝŽ";
+
+/// A pseudo-language, generated from the original messages, to test an
+/// application before it is actually translated.
+///
+/// Translations look like `[Ĥéļļö, {}! ~~~]`, which makes it easy to spot strings
+/// that aren't localized, truncated (no closing bracket) or don't support non-ASCII
+/// characters, while placeholders are left intact.
+///
+/// # Example
+///
+/// ```
+/// use crowbook_intl::{Localizer, Extractor, PseudoLang};
+/// let extractor = Extractor::new();
+/// let mut localizer = Localizer::new(&extractor);
+/// let mut pseudo = PseudoLang::new("en-XA");
+/// pseudo.set_expansion(50);
+/// localizer.add_pseudo_lang(pseudo);
+/// ```
+#[derive(Debug, Clone)]
+pub struct PseudoLang {
+    lang: String,
+    accents: bool,
+    // In percents of the original length
+    expansion: usize,
+    brackets: bool,
+    rtl: bool,
+}
+
+impl PseudoLang {
+    /// Creates a new pseudo-language with the given code (e.g. `en-XA`)
+    pub fn new<S: Into<String>>(lang: S) -> PseudoLang {
+        PseudoLang {
+            lang: lang.into(),
+            accents: true,
+            expansion: 30,
+            brackets: true,
+            rtl: false,
+        }
+    }
+
+    /// Returns the code of the pseudo-language
+    pub fn lang(&self) -> &str {
+        &self.lang
+    }
+
+    /// Sets whether ASCII letters are replaced by accented ones (e.g. `é` for `e`).
+    ///
+    /// Default is `true`.
+    pub fn set_accents(&mut self, accents: bool) -> &mut Self {
+        self.accents = accents;
+        self
+    }
+
+    /// Sets by how much (in percents of the original length) messages are
+    /// lengthened, to check that the UI copes with longer translations.
+    ///
+    /// Default is 30.
+    pub fn set_expansion(&mut self, percent: usize) -> &mut Self {
+        self.expansion = percent;
+        self
+    }
+
+    /// Sets whether messages are surrounded by brackets.
+    ///
+    /// Default is `true`.
+    pub fn set_brackets(&mut self, brackets: bool) -> &mut Self {
+        self.brackets = brackets;
+        self
+    }
+
+    /// Sets whether messages are displayed right-to-left (using Unicode
+    /// directional formatting characters), to test mirrored layouts.
+    ///
+    /// Default is `false`.
+    pub fn set_rtl(&mut self, rtl: bool) -> &mut Self {
+        self.rtl = rtl;
+        self
+    }
+
+    /// Returns the pseudo-translation of a message
+    ///
+    /// The message is considered as a format string: placeholders (e.g. `{}` or
+    /// `{name:>5}`) and escaped braces are kept as is.
+    pub fn translate(&self, msg: &str) -> String {
+        let mut res = String::with_capacity(msg.len() * 2);
+        if self.rtl {
+            res.push('\u{202E}');
+        }
+        if self.brackets {
+            res.push('[');
+        }
+        let mut text_len = 0;
+        let mut chars = msg.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' | '}' if chars.peek() == Some(&c) => {
+                    chars.next();
+                    res.push(c);
+                    res.push(c);
+                },
+                '{' => {
+                    res.push(c);
+                    for c in chars.by_ref() {
+                        res.push(c);
+                        if c == '}' {
+                            break;
+                        }
+                    }
+                },
+                c => {
+                    text_len += 1;
+                    res.push(if self.accents { accent(c) } else { c });
+                },
+            }
+        }
+        let padding = (text_len * self.expansion).div_ceil(100);
+        if padding > 0 {
+            res.push(' ');
+            res.push_str(&"~".repeat(padding - 1));
+        }
+        if self.brackets {
+            res.push(']');
+        }
+        if self.rtl {
+            res.push('\u{202C}');
+        }
+        res
+    }
+}
+
+/// Returns the accented version of an ASCII letter, or the character itself
+fn accent(c: char) -> char {
+    if let Some(i) = ASCII_LOWER.find(c) {
+        ACCENTED_LOWER.chars().nth(i).unwrap_or(c)
+    } else if let Some(i) = ASCII_UPPER.find(c) {
+        ACCENTED_UPPER.chars().nth(i).unwrap_or(c)
+    } else {
+        c
+    }
+}
+
+
+#[test]
+fn accent_tables() {
+    assert_eq!(ACCENTED_LOWER.chars().count(), 26);
+    assert_eq!(ACCENTED_UPPER.chars().count(), 26);
+    assert_eq!(accent('e'), 'é');
+    assert_eq!(accent('Z'), 'Ž');
+    assert_eq!(accent('!'), '!');
+}
+
+#[test]
+fn pseudo_translate() {
+    let pseudo = PseudoLang::new("en-XA");
+    assert_eq!(&pseudo.translate("Hello, {}!"), "[Ĥéļļö, {}! ~~]");
+    assert_eq!(&pseudo.translate("{{literal}} {name:>5} {0}"), "[{{ļîţéŕåļ}} {name:>5} {0} ~~]");
+    let mut pseudo = PseudoLang::new("ar-XB");
+    pseudo.set_accents(false).set_expansion(0).set_brackets(false).set_rtl(true);
+    assert_eq!(&pseudo.translate("Hello"), "\u{202E}Hello\u{202C}");
+}